@@ -0,0 +1,136 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use crate::transport::{Transport,connect_tcp};
+#[cfg(unix)]
+use crate::transport::connect_unix;
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConfig,connect_tls};
+#[cfg(feature = "websocket")]
+use crate::ws::connect_ws;
+use crate::errors::*;
+
+/// Where to reach the relay.
+///
+/// Parsed from strings like `unix:/path/to/socket`, `tcp://host:port`,
+/// `tls://host:port`, `ws://host:port/weechat` or `wss://host/weechat`.
+/// Addresses without a scheme are treated as `tcp://`.
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Address {
+    Unix(PathBuf),
+    Tcp { host: String, port: u16 },
+    Tls { host: String, port: u16 },
+    /// A `ws://` or `wss://` URL.
+    WebSocket(String),
+}
+
+impl Address {
+    /// Connects to the relay using the default configuration for the transport.
+    pub async fn connect(self) -> Result<Box<dyn Transport>> {
+        Ok(match self {
+            #[cfg(unix)]
+            Address::Unix(path) => Box::new(connect_unix(path).await?),
+            #[cfg(not(unix))]
+            Address::Unix(_) => bail!("UNIX domain sockets are not supported on this platform"),
+            Address::Tcp { host, port } => Box::new(connect_tcp(host, port).await?),
+            #[cfg(feature = "tls")]
+            Address::Tls { host, port } => Box::new(connect_tls(host, port, TlsConfig::new()).await?),
+            #[cfg(not(feature = "tls"))]
+            Address::Tls { .. } => bail!("tls:// requires the tls feature"),
+            #[cfg(feature = "websocket")]
+            Address::WebSocket(url) => Box::new(connect_ws(url).await?),
+            #[cfg(not(feature = "websocket"))]
+            Address::WebSocket(_) => bail!("ws:// requires the websocket feature"),
+        })
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            let path = path.trim_start_matches("//");
+            ensure!(!path.is_empty(), "missing socket path in '{}'", address);
+            return Ok(Address::Unix(path.into()));
+        }
+
+        if address.starts_with("ws://") || address.starts_with("wss://") {
+            return Ok(Address::WebSocket(address.into()));
+        }
+
+        if let Some(host_port) = address.strip_prefix("tls://") {
+            let (host, port) = parse_host_port(host_port)?;
+            return Ok(Address::Tls { host, port });
+        }
+
+        let host_port = address.strip_prefix("tcp://").unwrap_or(address);
+        ensure!(!host_port.contains("://"), "unsupported scheme in '{}'", address);
+        let (host, port) = parse_host_port(host_port)?;
+
+        Ok(Address::Tcp { host, port })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Unix(ref path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp { ref host, port } => write!(f, "tcp://{}:{}", format_host(host), port),
+            Address::Tls { ref host, port } => write!(f, "tls://{}:{}", format_host(host), port),
+            Address::WebSocket(ref url) => url.fmt(f),
+        }
+    }
+}
+
+/// Splits `host:port`, where `host` may be a bracketed IPv6 address.
+fn parse_host_port(host_port: &str) -> Result<(String, u16)> {
+    let host_port = host_port.trim_end_matches('/');
+    let colon = host_port.rfind(':').ok_or_else(|| format!("missing port in '{}'", host_port))?;
+    let host = host_port[..colon].trim_start_matches('[').trim_end_matches(']');
+    let port = host_port[colon + 1..].parse().chain_err(|| format!("invalid port in '{}'", host_port))?;
+
+    ensure!(!host.is_empty(), "missing host in '{}'", host_port);
+
+    Ok((host.into(), port))
+}
+
+fn format_host(host: &str) -> String {
+    if host.contains(':') { format!("[{}]", host) } else { host.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> Address {
+        Address::Tcp { host: host.into(), port }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("unix:/run/weechat/relay.sock".parse::<Address>().unwrap(), Address::Unix("/run/weechat/relay.sock".into()));
+        assert_eq!("unix:///tmp/relay".parse::<Address>().unwrap(), Address::Unix("/tmp/relay".into()));
+        assert_eq!("tcp://example.com:9001".parse::<Address>().unwrap(), tcp("example.com", 9001));
+        assert_eq!("127.0.0.1:9001".parse::<Address>().unwrap(), tcp("127.0.0.1", 9001));
+        assert_eq!("tcp://[::1]:9001".parse::<Address>().unwrap(), tcp("::1", 9001));
+        assert_eq!("tls://example.com:9001".parse::<Address>().unwrap(), Address::Tls { host: "example.com".into(), port: 9001 });
+        assert_eq!("wss://example.com/weechat".parse::<Address>().unwrap(), Address::WebSocket("wss://example.com/weechat".into()));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("unix:".parse::<Address>().is_err());
+        assert!("tcp://example.com".parse::<Address>().is_err());
+        assert!("tcp://:9001".parse::<Address>().is_err());
+        assert!("tls://example.com:port".parse::<Address>().is_err());
+        assert!("http://example.com:80".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        for address in &["unix:/tmp/relay", "tcp://[::1]:9001", "tls://example.com:9001", "ws://localhost:9001/weechat"] {
+            assert_eq!(address.parse::<Address>().unwrap().to_string(), *address);
+        }
+    }
+}
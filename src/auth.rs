@@ -0,0 +1,360 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+use sha2::{Sha256,Sha512,Digest};
+use pbkdf2::pbkdf2_hmac;
+use rand;
+use crate::totp;
+use crate::object::{HashTable,Object};
+use crate::raw::Compression;
+use crate::errors::*;
+
+/// Algorithms that can be used to hash the password during authentication.
+///
+/// The variants are ordered from weakest to strongest.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum PasswordHashAlgo {
+    Plain,
+    Sha256,
+    Sha512,
+    Pbkdf2Sha256,
+    Pbkdf2Sha512,
+}
+
+impl PasswordHashAlgo {
+    pub const ALL: &'static [PasswordHashAlgo] = &[
+        PasswordHashAlgo::Plain,
+        PasswordHashAlgo::Sha256,
+        PasswordHashAlgo::Sha512,
+        PasswordHashAlgo::Pbkdf2Sha256,
+        PasswordHashAlgo::Pbkdf2Sha512,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PasswordHashAlgo::Plain        => "plain",
+            PasswordHashAlgo::Sha256       => "sha256",
+            PasswordHashAlgo::Sha512       => "sha512",
+            PasswordHashAlgo::Pbkdf2Sha256 => "pbkdf2+sha256",
+            PasswordHashAlgo::Pbkdf2Sha512 => "pbkdf2+sha512",
+        }
+    }
+}
+
+impl FromStr for PasswordHashAlgo {
+    type Err = Error;
+
+    fn from_str(algo: &str) -> Result<Self> {
+        Ok(match algo {
+            "plain"         => PasswordHashAlgo::Plain,
+            "sha256"        => PasswordHashAlgo::Sha256,
+            "sha512"        => PasswordHashAlgo::Sha512,
+            "pbkdf2+sha256" => PasswordHashAlgo::Pbkdf2Sha256,
+            "pbkdf2+sha512" => PasswordHashAlgo::Pbkdf2Sha512,
+            _ => bail!(ErrorKind::UnsupportedPasswordHashAlgo(algo.into())),
+        })
+    }
+}
+
+/// Options for authenticating with the relay.
+///
+/// Can be created from a plain password, which allows all password hash algorithms.
+#[derive(Clone,Debug)]
+pub struct AuthOptions {
+    password: String,
+    password_hash_algos: Vec<PasswordHashAlgo>,
+    handshake: bool,
+    handshake_timeout: Duration,
+    totp: Option<Totp>,
+    compression: Compression,
+}
+
+impl AuthOptions {
+    pub fn new<P: Into<String>>(password: P) -> Self {
+        AuthOptions {
+            password: password.into(),
+            password_hash_algos: PasswordHashAlgo::ALL.to_vec(),
+            handshake: true,
+            handshake_timeout: Duration::from_secs(5),
+            totp: None,
+            compression: Compression::Off,
+        }
+    }
+
+    /// Restricts the password hash algorithms offered to the relay.
+    ///
+    /// The relay picks the strongest algorithm it supports.
+    pub fn password_hash_algos(mut self, algos: &[PasswordHashAlgo]) -> Self {
+        self.password_hash_algos = algos.to_vec();
+        self
+    }
+
+    /// Whether to negotiate the password hash algorithm with a `handshake`.
+    ///
+    /// Relays older than WeeChat 3.5 don't support the handshake
+    /// and only accept the plain password. Enabled by default.
+    pub fn handshake(mut self, handshake: bool) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    /// How long to wait for the response to the `handshake`
+    /// before authenticating with the plain password. 5 seconds by default.
+    ///
+    /// Relays older than WeeChat 3.5 ignore the handshake instead of rejecting it.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Sends a time-based one-time password as second factor.
+    pub fn totp(mut self, totp: Totp) -> Self {
+        self.totp = Some(totp);
+        self
+    }
+
+    /// Requests the relay to compress its messages. Disabled by default.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub(crate) fn requested_compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub(crate) fn uses_totp(&self) -> bool {
+        self.totp.is_some()
+    }
+
+    /// Whether the TOTP is a code that expires, so it can't be sent again later.
+    pub(crate) fn uses_totp_code(&self) -> bool {
+        matches!(self.totp, Some(Totp::Code(_)))
+    }
+
+    pub(crate) fn uses_handshake(&self) -> bool {
+        self.handshake
+    }
+
+    pub(crate) fn requested_handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    pub(crate) fn allowed_password_hash_algos(&self) -> &[PasswordHashAlgo] {
+        &self.password_hash_algos
+    }
+
+    /// Builds the authentication arguments of the `init` command.
+    pub(crate) fn init_args(&self, handshake: Option<&Handshake>) -> Result<String> {
+        let password = self.init_password(handshake)?;
+
+        if let Some(handshake) = handshake {
+            ensure!(!handshake.totp || self.totp.is_some(), ErrorKind::TotpRequired);
+        }
+
+        Ok(match self.totp {
+            Some(ref totp) => format!("{},totp={}", password, totp.code()?),
+            None => password,
+        })
+    }
+
+    fn init_password(&self, handshake: Option<&Handshake>) -> Result<String> {
+        let handshake = match handshake {
+            None => return Ok(format!("password={}", escape(&self.password))),
+            Some(handshake) => handshake,
+        };
+
+        ensure!(
+            self.password_hash_algos.contains(&handshake.password_hash_algo),
+            ErrorKind::UnsupportedPasswordHashAlgo(handshake.password_hash_algo.as_str().into())
+        );
+
+        if handshake.password_hash_algo == PasswordHashAlgo::Plain {
+            return Ok(format!("password={}", escape(&self.password)));
+        }
+
+        let client_nonce = rand::random::<[u8; 16]>();
+        let mut salt = handshake.nonce.clone();
+        salt.extend_from_slice(&client_nonce);
+
+        Ok(format!("password_hash={}", password_hash(
+            handshake.password_hash_algo,
+            &self.password,
+            &salt,
+            handshake.password_hash_iterations,
+        )))
+    }
+}
+
+impl From<String> for AuthOptions {
+    fn from(password: String) -> Self {
+        AuthOptions::new(password)
+    }
+}
+
+impl From<&str> for AuthOptions {
+    fn from(password: &str) -> Self {
+        AuthOptions::new(password)
+    }
+}
+
+/// A time-based one-time password (TOTP) used as second factor.
+#[derive(Clone,Debug)]
+pub enum Totp {
+    /// A code that was already generated, e.g. entered by the user.
+    Code(String),
+    /// The base32 encoded secret to generate the current code from.
+    Secret(String),
+}
+
+impl Totp {
+    fn code(&self) -> Result<String> {
+        match *self {
+            Totp::Code(ref code) => Ok(code.clone()),
+            Totp::Secret(ref secret) => totp::current_code(secret),
+        }
+    }
+}
+
+/// The parameters negotiated with the `handshake` command.
+#[derive(Clone,Debug)]
+pub(crate) struct Handshake {
+    password_hash_algo: PasswordHashAlgo,
+    password_hash_iterations: u32,
+    nonce: Vec<u8>,
+    totp: bool,
+    compression: Compression,
+}
+
+impl Handshake {
+    pub(crate) fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+impl TryFrom<HashTable> for Handshake {
+    type Error = Error;
+
+    fn try_from(response: HashTable) -> Result<Self> {
+        let get = |key: &str| {
+            response.get(&Object::str(key))
+                .and_then(Object::as_str)
+                .ok_or_else(|| Error::from(ErrorKind::MissingKey(key.into())))
+        };
+
+        let algo = get("password_hash_algo")?;
+        ensure!(!algo.is_empty(), ErrorKind::UnsupportedPasswordHashAlgo(algo.into()));
+
+        Ok(Handshake {
+            password_hash_algo: algo.parse()?,
+            password_hash_iterations: get("password_hash_iterations")?.parse()
+                .chain_err(|| "decoding password_hash_iterations")?,
+            nonce: from_hex(get("nonce")?).chain_err(|| "decoding nonce")?,
+            totp: get("totp").map(|totp| totp == "on").unwrap_or(false),
+            compression: match get("compression") {
+                Ok(compression) => compression.parse()?,
+                Err(_) => Compression::Off,
+            },
+        })
+    }
+}
+
+/// Formats the `password_hash` argument of the `init` command.
+fn password_hash(algo: PasswordHashAlgo, password: &str, salt: &[u8], iterations: u32) -> String {
+    let salt_hex = to_hex(salt);
+
+    match algo {
+        PasswordHashAlgo::Plain => password.into(),
+        PasswordHashAlgo::Sha256 => {
+            let hash = Sha256::new().chain_update(salt).chain_update(password.as_bytes()).finalize();
+            format!("sha256:{}:{}", salt_hex, to_hex(&hash))
+        },
+        PasswordHashAlgo::Sha512 => {
+            let hash = Sha512::new().chain_update(salt).chain_update(password.as_bytes()).finalize();
+            format!("sha512:{}:{}", salt_hex, to_hex(&hash))
+        },
+        PasswordHashAlgo::Pbkdf2Sha256 => {
+            let mut hash = [0; 32];
+            pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+            format!("pbkdf2+sha256:{}:{}:{}", salt_hex, iterations, to_hex(&hash))
+        },
+        PasswordHashAlgo::Pbkdf2Sha512 => {
+            let mut hash = [0; 64];
+            pbkdf2_hmac::<Sha512>(password.as_bytes(), salt, iterations, &mut hash);
+            format!("pbkdf2+sha512:{}:{}:{}", salt_hex, iterations, to_hex(&hash))
+        },
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace(",", r"\,")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    ensure!(hex.len().is_multiple_of(2), "odd number of hex digits");
+
+    // Iterates over bytes, since slicing could split a multibyte character
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digit = |byte: u8| (byte as char).to_digit(16).ok_or_else(|| Error::from("invalid hex digit"));
+            Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: &str = "85b1ee00695a5b254e14f4885538df0da4b73207f5aae4";
+
+    fn hash(algo: PasswordHashAlgo, iterations: u32) -> String {
+        password_hash(algo, "test", &from_hex(SALT).unwrap(), iterations)
+    }
+
+    #[test]
+    fn sha256() {
+        assert_eq!(
+            hash(PasswordHashAlgo::Sha256, 0),
+            format!("sha256:{}:2c6ed12eb0109fca3aedc03bf03d9b6e804cd60a23e1731fd17794da423e21db", SALT)
+        );
+    }
+
+    #[test]
+    fn sha512() {
+        assert_eq!(
+            hash(PasswordHashAlgo::Sha512, 0),
+            format!("sha512:{}:0a1f0172a542916bd86e0cbceebc1c38ed791f6be246120452825f0d74ef1078\
+                c79e9812de8b0ab3dfaf598b6ca14522374ec6a8653a46df3f96a6b54ac1f0f8", SALT)
+        );
+    }
+
+    #[test]
+    fn pbkdf2_sha256() {
+        assert_eq!(
+            hash(PasswordHashAlgo::Pbkdf2Sha256, 100000),
+            format!("pbkdf2+sha256:{}:100000:ba7facc3edb89cd06ae810e29ced85980ff36de2bb596fcf513aaab626876440", SALT)
+        );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(from_hex("00ff1A").unwrap(), vec![0x00, 0xff, 0x1a]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("éa").is_err());
+        assert!(from_hex("éé").is_err());
+    }
+
+    #[test]
+    fn algo_order() {
+        assert_eq!(PasswordHashAlgo::ALL.iter().max(), Some(&PasswordHashAlgo::Pbkdf2Sha512));
+        assert_eq!("pbkdf2+sha256".parse::<PasswordHashAlgo>().unwrap(), PasswordHashAlgo::Pbkdf2Sha256);
+        assert!("md5".parse::<PasswordHashAlgo>().is_err());
+    }
+}
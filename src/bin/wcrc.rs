@@ -1,38 +1,32 @@
 #![allow(warnings)]
-#![feature(proc_macro, conservative_impl_trait, generators)]
-extern crate weechat_relay;
-extern crate tokio_core;
-extern crate futures_await as futures;
-extern crate error_chain;
-
 use std::env;
-use tokio_core::reactor::{Core,Handle};
-use tokio_core::net::TcpStream;
-use futures::prelude::*;
 use error_chain::ChainedError;
-use weechat_relay::{Client,Object,Result,ResultExt};
+use weechat_relay::{Address,Client,Object,Result,ResultExt};
 
 fn main() {
-    let mut core = Core::new().unwrap();
-    let handle = core.handle();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the tokio runtime");
 
-    if let Err(e) = core.run(async_main(handle)) {
+    if let Err(e) = runtime.block_on(async_main()) {
         println!("{}", e.display_chain());
     }
 }
 
-#[async]
-fn async_main(handle: Handle) -> Result<()> {
+async fn async_main() -> Result<()> {
     let pass = env::var("WCP").expect("Set the WCP env var to your relay password");
 
-    let addr = "192.168.2.11:3143".parse().unwrap();
-    let conn = await!(TcpStream::connect(&addr, &handle))?;
+    let addr: Address = env::args().nth(1)
+        .unwrap_or_else(|| "192.168.2.11:3143".into())
+        .parse()?;
+    let conn = addr.connect().await?;
 
-    let client = await!(Client::auth(handle, conn, pass))?;
+    let client = Client::auth(conn, pass).await?;
 
     println!("Ready.");
 
-    let buffer = await!(client.infolist("buffer")).chain_err(|| "Error")?;
+    let buffer = client.infolist("buffer").await.chain_err(|| "Error")?;
 
     for (i, item) in buffer.items().iter().enumerate() {
         if let Some(value) = item.get(&Object::str("full_name")) {
@@ -51,7 +45,7 @@ fn async_main(handle: Handle) -> Result<()> {
 
     //     stdin.read_line(&mut input);
 
-    //     let pong = await!(client.ping(input)).unwrap();
+    //     let pong = client.ping(input).await.unwrap();
 
     //     println!("{:?}", pong);
     // }
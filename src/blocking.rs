@@ -0,0 +1,282 @@
+//! A synchronous client for programs without an async runtime.
+//!
+//! ```ignore
+//! let mut client = BlockingClient::connect("127.0.0.1:9001", "secret")?;
+//! let buffers = client.infolist("buffer")?;
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self,Read,Write};
+use std::net::{TcpStream,ToSocketAddrs};
+use crate::auth::AuthOptions;
+use crate::command::{SyncOption,BufferId};
+use crate::connection::{Connection,Message};
+use crate::message_resolver::FromResponse;
+use crate::object;
+use crate::event::Event;
+use crate::completion::Completion;
+use crate::nicklist::Nicklist;
+use crate::errors::*;
+
+/// A client that blocks until the response to each request arrives.
+///
+/// Events received while waiting for a response are queued until taken with `next_event`.
+pub struct BlockingClient<S = TcpStream> {
+    stream: S,
+    connection: Connection,
+    events: VecDeque<Event>,
+    buf: Vec<u8>,
+    /// Called once the handshake is done, e.g. to clear the read timeout set for it.
+    handshake_done: Option<fn(&S) -> io::Result<()>>,
+}
+
+impl BlockingClient {
+    /// Connects to the relay at `addr` and authenticates.
+    ///
+    /// `options` can be a plain password or an `AuthOptions`.
+    pub fn connect<T: ToSocketAddrs, A: Into<AuthOptions>>(addr: T, options: A) -> Result<Self> {
+        let options = options.into();
+        let stream = TcpStream::connect(addr).chain_err(|| "connecting to relay")?;
+
+        // Old relays never respond to the handshake, but hashing the password may take longer
+        stream.set_read_timeout(Some(options.requested_handshake_timeout()))?;
+        BlockingClient::authenticate(stream, options, Some(|stream: &TcpStream| stream.set_read_timeout(None)))
+    }
+}
+
+impl<S: Read + Write> BlockingClient<S> {
+    /// Authenticates with the relay on `stream`.
+    ///
+    /// If reading from `stream` times out while waiting for the response to the handshake,
+    /// the plain password is sent instead, see `AuthOptions::handshake_timeout`.
+    /// Fails like `Client::auth` if the relay rejects the credentials.
+    pub fn auth<A: Into<AuthOptions>>(stream: S, options: A) -> Result<Self> {
+        Self::authenticate(stream, options, None)
+    }
+
+    fn authenticate<A: Into<AuthOptions>>(stream: S, options: A, handshake_done: Option<fn(&S) -> io::Result<()>>) -> Result<Self> {
+        let options = options.into();
+        // The relay doesn't tell whether the password or the TOTP was wrong
+        let totp = options.uses_totp();
+        let auth_failed = move || if totp { ErrorKind::TotpRejected } else { ErrorKind::AuthFailed };
+
+        let mut client = BlockingClient {
+            stream,
+            connection: Connection::new(options)?,
+            events: VecDeque::new(),
+            buf: vec![0; 8192],
+            handshake_done,
+        };
+
+        loop {
+            match client.receive().chain_err(auth_failed)? {
+                Message::Authenticated => return Ok(client),
+                message => client.dispatch(message),
+            }
+        }
+    }
+
+    /// The underlying stream, e.g. to set timeouts.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn ping<M: Into<String>>(&mut self, msg: M) -> Result<String> {
+        self.connection.ping(msg)?;
+
+        loop {
+            match self.receive()? {
+                Message::Pong(pong) => return Ok(pong),
+                message => self.dispatch(message),
+            }
+        }
+    }
+
+    pub fn info<N: Into<String>>(&mut self, name: N) -> Result<object::Info> {
+        let id = self.connection.info(name)?;
+        self.response(id)
+    }
+
+    pub fn infolist<N: Into<String>>(&mut self, name: N) -> Result<object::InfoList> {
+        let id = self.connection.infolist(name)?;
+        self.response(id)
+    }
+
+    /// Requests the hdata at `path`, restricted to `keys` unless empty.
+    pub fn hdata<P: Into<String>, K: AsRef<str>>(&mut self, path: P, keys: &[K]) -> Result<object::HData> {
+        let id = self.connection.hdata(path, keys)?;
+        self.response(id)
+    }
+
+    /// Starts receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    pub fn sync<B: AsRef<str>>(&mut self, buffers: &[B], options: &[SyncOption]) -> Result<()> {
+        self.connection.sync(buffers, options)?;
+        self.flush()
+    }
+
+    /// Stops receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    pub fn desync<B: AsRef<str>>(&mut self, buffers: &[B], options: &[SyncOption]) -> Result<()> {
+        self.connection.desync(buffers, options)?;
+        self.flush()
+    }
+
+    /// Sends `text` to `buffer` as if it was typed by the user.
+    ///
+    /// See `Client::input` for how multiple lines are sent.
+    pub fn input<B: Into<BufferId>, T: Into<String>>(&mut self, buffer: B, text: T) -> Result<()> {
+        self.connection.input(buffer, text)?;
+        self.flush()
+    }
+
+    /// Requests completion of `data` at `position` as if it was typed in `buffer`.
+    pub fn completion<B: Into<BufferId>, D: Into<String>>(&mut self, buffer: B, position: i32, data: D) -> Result<Option<Completion>> {
+        let id = self.connection.completion(buffer, position, data)?;
+        self.response(id)
+    }
+
+    /// Requests the nicklist of `buffer`, or of all buffers if `buffer` is `None`.
+    pub fn nicklist(&mut self, buffer: Option<BufferId>) -> Result<Nicklist> {
+        let id = self.connection.nicklist(buffer)?;
+        self.response(id)
+    }
+
+    /// Blocks until the next event of a synced buffer arrives.
+    pub fn next_event(&mut self) -> Result<Event> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+
+            let message = self.receive()?;
+            self.dispatch(message);
+        }
+    }
+
+    fn response<T: FromResponse>(&mut self, id: String) -> Result<T> {
+        loop {
+            match self.receive()? {
+                Message::Response(ref actual, response) if *actual == id => return response.and_then(T::from_response),
+                message => self.dispatch(message),
+            }
+        }
+    }
+
+    /// Keeps messages nobody is waiting for.
+    fn dispatch(&mut self, message: Message) {
+        match message {
+            Message::Event(event) => self.events.push_back(event),
+            message => debug!(?message, "dropping message"),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let outgoing = self.connection.take_outgoing();
+        self.stream.write_all(&outgoing).chain_err(|| "sending command")?;
+        self.stream.flush()?;
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Message> {
+        loop {
+            if let Some(message) = self.connection.next_message().chain_err(|| "decoding message")? {
+                return Ok(message);
+            }
+
+            // Decoding may have queued commands, e.g. `init` after the handshake
+            self.flush()?;
+
+            if self.connection.handshake_timeout().is_none() {
+                if let Some(handshake_done) = self.handshake_done.take() {
+                    handshake_done(&self.stream)?;
+                }
+            }
+
+            let len = match self.stream.read(&mut self.buf) {
+                Err(ref err) if is_timeout(err) && self.connection.handshake_timeout().is_some() => {
+                    self.connection.skip_handshake()?;
+                    continue;
+                },
+                len => len.chain_err(|| "reading message")?,
+            };
+
+            // The relay closed the connection
+            ensure!(len > 0, ErrorKind::Disconnected);
+
+            self.connection.feed(&self.buf[..len]);
+        }
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use futures::future;
+    use crate::object::{Object,HData};
+    use crate::testing::{MockRelay,Response};
+
+    /// Runs `relay` on a background thread.
+    fn listen(relay: MockRelay) -> SocketAddr {
+        let (addr_tx, addr_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                addr_tx.send(relay.listen().await.unwrap()).unwrap();
+                future::pending::<()>().await
+            });
+        });
+
+        addr_rx.recv().unwrap()
+    }
+
+    #[test]
+    fn info() {
+        let relay = MockRelay::new("secret")
+            .on("info", |req| vec![
+                Response::new("_buffer_cleared", vec![Object::hdata(HData::new("buffer", vec![], vec![]))]),
+                Response::reply(req, vec![Object::info((req.args.as_str(), "4.0.0"))]),
+            ]);
+        let mut client = BlockingClient::connect(listen(relay), "secret").unwrap();
+
+        let info = client.info("version").unwrap();
+        assert_eq!(info.value(), "4.0.0");
+        assert_eq!(client.ping("hi").unwrap(), "hi");
+
+        // The event received before the response is kept
+        match client.next_event().unwrap() {
+            Event::BufferCleared(_) => {},
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn slow_auth() {
+        let relay = MockRelay::new("secret").auth_delay(Duration::from_millis(100));
+        let options = AuthOptions::new("secret").handshake_timeout(Duration::from_millis(20));
+
+        // The handshake timeout doesn't apply to the authentication
+        let mut client = BlockingClient::connect(listen(relay), options).unwrap();
+        assert_eq!(client.get_ref().read_timeout().unwrap(), None);
+        assert_eq!(client.ping("hi").unwrap(), "hi");
+    }
+
+    #[test]
+    fn auth_failed() {
+        let relay = MockRelay::new("secret");
+
+        match BlockingClient::connect(listen(relay), "wrong") {
+            Err(Error(ErrorKind::AuthFailed, _)) => {},
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("authenticated with a wrong password"),
+        }
+    }
+}
@@ -1,116 +1,496 @@
+use futures::channel::mpsc::{unbounded,UnboundedSender,UnboundedReceiver};
+use futures::channel::oneshot::{self,Receiver,Canceled};
+use futures::future::{self,Either};
+use futures::pin_mut;
 use futures::prelude::*;
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncWrite,AsyncWriteExt};
+use tokio::time::Instant;
+use tracing::Instrument;
 use error_chain::ChainedError;
-use errors::*;
-use tokio_core::reactor::Handle;
-use tokio_io::{AsyncRead,AsyncWrite};
-use tokio_io::io::{ReadHalf};
-use raw::Compression;
-use raw;
-use command::{self,Command};
-use message::{self,Message};
-use message_resolver::{MessageResolver,Resolver};
-use object;
-use futures::sync::mpsc::{unbounded,UnboundedSender,UnboundedReceiver};
-use futures::sync::oneshot::{channel,Sender,Receiver};
-use typemap::{TypeMap,Key};
-use std::rc::Rc;
-use std::cell::{Cell,RefCell};
+use crate::errors::*;
+use crate::auth::AuthOptions;
+use crate::command::{self,Command,RequestIds};
+use crate::connection::Connection;
+pub use crate::command::{SyncOption,BufferId};
+use crate::message_resolver::{MessageResolver,Resolver};
+use crate::object;
+use crate::event::Event;
+use crate::completion::Completion;
+use crate::nicklist::Nicklist;
+use crate::state::State;
+use crate::lines::{Lines,Line};
+use crate::hotlist::Hotlist;
+use crate::object::Pointer;
 use std::convert::TryFrom;
-use std::marker::PhantomData;
-use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc,Mutex};
+use std::task::{Context,Poll};
 
 pub struct Client {
-    id_counter: Rc<Cell<usize>>,
+    ids: RequestIds,
     command_tx: UnboundedSender<Command>,
-    message_resolver: Rc<RefCell<MessageResolver>>,
+    message_resolver: Arc<Mutex<MessageResolver>>,
 }
 
 impl Client {
-    #[async]
-    pub fn auth<S,P>(handle: Handle, stream: S, pass: P) -> Result<Self> where
-        S: AsyncRead + AsyncWrite + 'static,
-        P: Into<String> + 'static,
+    /// Authenticates with the relay on `stream`.
+    ///
+    /// `options` can be a plain password or an `AuthOptions`.
+    /// The connection is served by a task spawned on the current tokio runtime.
+    ///
+    /// Fails with `ErrorKind::AuthFailed` if the relay rejects the password,
+    /// or with `ErrorKind::TotpRejected` if a TOTP was sent, since then either one could be wrong.
+    pub async fn auth<S,A>(stream: S, options: A) -> Result<Self> where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+        A: Into<AuthOptions>,
     {
+        let options = options.into();
+        // The relay doesn't tell whether the password or the TOTP was wrong
+        let auth_failed = if options.uses_totp() { ErrorKind::TotpRejected } else { ErrorKind::AuthFailed };
+        let connection = Connection::new(options)?;
         let (command_tx, command_rx) = unbounded();
-        
+
         let client = Client {
-            id_counter: Rc::new(Cell::new(0)),
+            ids: connection.request_ids(),
             command_tx,
-            message_resolver: Rc::new(RefCell::new(MessageResolver::new())),
+            message_resolver: Arc::new(Mutex::new(MessageResolver::new())),
         };
 
-        let (reader, writer) = stream.split();
-        let writer = await!(raw::send_init(writer, pass.into(), Compression::Off))?;
-        let command_sender = command_sender(writer, command_rx);
-        let message_receiver = message_receiver(reader, client.message_resolver.clone());
-        let tasks = command_sender
-            .select(message_receiver)
-            .map(|_| ())
-            .map_err(|_| ());
+        let authenticated = client.message_resolver.lock().unwrap().register_auth();
+        let message_resolver = client.message_resolver.clone();
+        let connection = async move {
+            let reason = run(stream, connection, command_rx, message_resolver.clone()).await;
+
+            if let Err(ref err) = reason {
+                debug!(reason = %err.display_chain(), "connection closed");
+            }
 
-        handle.spawn(tasks);
+            message_resolver.lock().unwrap().close(reason);
+        };
 
-        await!(client.ping("auth")).chain_err(|| ErrorKind::AuthFailed)?;
+        tokio::spawn(connection.instrument(debug_span!("connection")));
 
-        println!("Auth success!");
+        authenticated.await.chain_err(|| auth_failed)?;
 
         Ok(client)
     }
 
-    fn new_id(&self) -> String{
-        let id = self.id_counter.get();
-        self.id_counter.set(id + 1);
-        id.to_string()
+    /// Checks `command` and queues it for sending.
+    ///
+    /// Commands that can't be encoded are rejected here, so the connection doesn't fail on them later.
+    fn send(&self, command: Command) -> Result<()> {
+        command.check()?;
+        self.queue(command);
+        Ok(())
+    }
+
+    /// Queues an already checked `command` for sending.
+    ///
+    /// Fails silently if the connection is closed,
+    /// promises are rejected by the `MessageResolver` in that case.
+    fn queue(&self, command: Command) {
+        let _ = self.command_tx.unbounded_send(command);
     }
 
-    pub fn ping<S: Into<String>>(&self, msg: S) -> Receiver<String> {
-        self.command_tx.unbounded_send(command::Ping(msg.into()).into());
-        self.message_resolver.borrow_mut().register_pong()
+    /// Sends `command` and returns a promise for the response with `id`.
+    fn request<T>(&self, id: String, command: Command) -> Promise<T> where
+        oneshot::Sender<Result<T>>: Resolver,
+        T: Send + 'static,
+    {
+        if let Err(err) = command.check() {
+            return Promise::rejected(err);
+        }
+
+        let promise = self.message_resolver.lock().unwrap().register_promise(id);
+        self.queue(command);
+        promise
+    }
+
+    fn new_id(&self) -> String {
+        self.ids.next()
     }
 
-    pub fn infolist<S: Into<String>>(&self, name: S) -> Receiver<object::InfoList> {
+    pub fn ping<S: Into<String>>(&self, msg: S) -> Promise<String> {
+        let command: Command = command::Ping(msg.into()).into();
+        if let Err(err) = command.check() {
+            return Promise::rejected(err);
+        }
+
+        // Pongs are matched in order, so registering and sending must not be interleaved
+        let mut message_resolver = self.message_resolver.lock().unwrap();
+        let promise = message_resolver.register_pong();
+        self.queue(command);
+        promise
+    }
+
+    pub fn info<S: Into<String>>(&self, name: S) -> Promise<object::Info> {
+        let id = self.new_id();
+        self.request(id.clone(), command::Info(id, name.into()).into())
+    }
+
+    pub fn infolist<S: Into<String>>(&self, name: S) -> Promise<object::InfoList> {
         let id = self.new_id();
-        self.command_tx.unbounded_send(command::InfoList(id.clone(), name.into()).into());
-        self.message_resolver.borrow_mut().register_promise(id)
+        self.request(id.clone(), command::InfoList(id, name.into()).into())
+    }
+
+    /// Starts receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    ///
+    /// Events are delivered to the streams returned by `events`.
+    /// Fails with `ErrorKind::InvalidArgument` if a buffer name contains a line break.
+    pub fn sync<S: AsRef<str>>(&self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let buffers = buffers.iter().map(|buffer| buffer.as_ref().into()).collect();
+        self.send(command::Sync(buffers, options.to_vec()).into())
+    }
+
+    /// Stops receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    ///
+    /// Fails like `sync`.
+    pub fn desync<S: AsRef<str>>(&self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let buffers = buffers.iter().map(|buffer| buffer.as_ref().into()).collect();
+        self.send(command::Desync(buffers, options.to_vec()).into())
+    }
+
+    /// Sends `text` to `buffer` as if it was typed by the user.
+    ///
+    /// Each line of `text` is sent as a separate input.
+    /// Empty lines are skipped and line endings, including `\r\n`, are not sent.
+    ///
+    /// Fails with `ErrorKind::Disconnected` if the connection is closed,
+    /// or with `ErrorKind::InvalidArgument` if the buffer name contains a line break.
+    pub fn input<B: Into<BufferId>, S: Into<String>>(&self, buffer: B, text: S) -> Result<()> {
+        let command = Command::from(command::Input(buffer.into(), text.into()));
+        command.check()?;
+        self.command_tx.unbounded_send(command)
+            .map_err(|_| ErrorKind::Disconnected.into())
+    }
+
+    /// Requests completion of `data` at `position` as if it was typed in `buffer`.
+    ///
+    /// A `position` of `-1` completes at the end of `data`.
+    /// Resolves to `None` if nothing can be completed.
+    pub fn completion<B: Into<BufferId>, S: Into<String>>(&self, buffer: B, position: i32, data: S) -> Promise<Option<Completion>> {
+        let id = self.new_id();
+        self.request(id.clone(), command::Completion(id, buffer.into(), position, data.into()).into())
+    }
+
+    /// Requests the nicklist of `buffer`, or of all buffers if `buffer` is `None`.
+    pub fn nicklist(&self, buffer: Option<BufferId>) -> Promise<Nicklist> {
+        let id = self.new_id();
+        self.request(id.clone(), command::Nicklist(id, buffer).into())
+    }
+
+    /// Returns a stream of all events received from now on.
+    pub fn events(&self) -> UnboundedReceiver<Event> {
+        self.message_resolver.lock().unwrap().subscribe_events()
+    }
+
+    /// Returns a future that resolves once the connection to the relay is closed.
+    ///
+    /// Fails with the reason if the connection was closed because of an error,
+    /// e.g. an I/O error or a message that couldn't be decoded.
+    /// All requests that are still pending at that point fail with `ErrorKind::Disconnected`.
+    pub fn closed(&self) -> Promise<()> {
+        self.message_resolver.lock().unwrap().subscribe_closed()
+    }
+
+    /// Requests the hdata at `path`, e.g. `buffer:gui_buffers(*)/lines/first_line(*)/data`.
+    ///
+    /// The returned request sends all keys unless restricted with `HDataRequest::keys`.
+    pub fn hdata<S: Into<String>>(&self, path: S) -> HDataRequest {
+        HDataRequest {
+            id: self.new_id(),
+            path: path.into(),
+            keys: Vec::new(),
+            command_tx: self.command_tx.clone(),
+            message_resolver: self.message_resolver.clone(),
+            response: None,
+        }
+    }
+
+    /// Fetches all buffers to be kept up to date with `State::apply`.
+    pub async fn fetch_state(&self) -> Result<State> {
+        let hdata = self.hdata(State::HDATA_PATH).keys(State::HDATA_KEYS).await?;
+        State::try_from(&hdata).chain_err(|| "decoding buffers")
+    }
+
+    /// Fetches the last `count` lines of `buffer`, oldest first.
+    ///
+    /// Merge them with the lines received while synced using `State::merge_backlog` or `Lines::merge`.
+    pub async fn fetch_backlog(&self, buffer: &Pointer, count: usize) -> Result<Vec<Line>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let path = format!("buffer:{}/own_lines/last_line(-{})/data", buffer, count);
+        let hdata = self.hdata(path).keys(Lines::HDATA_KEYS).await?;
+
+        // The lines are sent starting with the last one
+        hdata.items().iter().rev()
+            .map(Line::try_from)
+            .collect::<Result<_>>()
+            .chain_err(|| "decoding lines")
+    }
+
+    /// Fetches the unread counts of all buffers to be kept up to date with `Hotlist::apply`.
+    pub async fn fetch_hotlist(&self) -> Result<Hotlist> {
+        let hdata = self.hdata(Hotlist::HDATA_PATH).keys(Hotlist::HDATA_KEYS).await?;
+        Hotlist::try_from(&hdata).chain_err(|| "decoding hotlist")
+    }
+
+    /// Removes `buffer` from the hotlist of WeeChat and moves its read marker to the last line.
+    ///
+    /// The local `Hotlist` is not changed, use `Hotlist::remove` for that.
+    pub fn mark_read<B: Into<BufferId>>(&self, buffer: B) -> Result<()> {
+        self.input(buffer, "/buffer set hotlist -1\n/input set_unread_current_buffer")
     }
 }
 
-#[async]
-fn command_sender<W>(mut writer: W, command_rx: UnboundedReceiver<Command>) -> Result<()> where
-    W: AsyncWrite + 'static,
-{
-    #[async]
-    for command in command_rx.map_err(|_| ErrorKind::Disconnected) {
-        writer = await!(command.send_raw(writer))?;
+/// A pending `hdata` request.
+///
+/// The command is only sent once the request is polled for the first time.
+#[must_use = "futures do nothing unless polled"]
+pub struct HDataRequest {
+    id: String,
+    path: String,
+    keys: Vec<String>,
+    command_tx: UnboundedSender<Command>,
+    message_resolver: Arc<Mutex<MessageResolver>>,
+    response: Option<Promise<object::HData>>,
+}
+
+impl HDataRequest {
+    /// Restricts the response to the given keys.
+    pub fn keys<S: AsRef<str>>(mut self, keys: &[S]) -> Self {
+        self.keys = keys.iter().map(|key| key.as_ref().into()).collect();
+        self
+    }
+}
+
+impl Future for HDataRequest {
+    type Output = Result<object::HData>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.response.is_none() {
+            let command = Command::from(command::HData(self.id.clone(), self.path.clone(), self.keys.clone()));
+            let response = match command.check() {
+                Ok(()) => {
+                    let response = self.message_resolver.lock().unwrap().register_promise(self.id.clone());
+                    // See `Client::send`
+                    let _ = self.command_tx.unbounded_send(command);
+                    response
+                },
+                Err(err) => Promise::rejected(err),
+            };
+            self.response = Some(response);
+        }
+
+        Pin::new(self.response.as_mut().unwrap()).poll(cx)
+    }
+}
+
+/// The response to a request.
+///
+/// Fails with `ErrorKind::Disconnected` if the connection is closed before the response arrives.
+/// The reason the connection was closed, if any, is part of the error chain.
+#[must_use = "futures do nothing unless polled"]
+pub struct Promise<T> {
+    response: Receiver<Result<T>>,
+}
+
+impl<T> Promise<T> {
+    pub(crate) fn new(response: Receiver<Result<T>>) -> Self {
+        Promise { response }
+    }
+
+    /// A promise that fails with `err` right away.
+    pub(crate) fn rejected(err: Error) -> Self {
+        let (tx, rx) = oneshot::channel();
+        let _ = tx.send(Err(err));
+        Promise::new(rx)
     }
+}
 
-    println!("Quitting sender :(");
+impl<T> Future for Promise<T> {
+    type Output = Result<T>;
 
-    Ok(())
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.response).poll(cx) {
+            Poll::Ready(Ok(response)) => Poll::Ready(response),
+            Poll::Ready(Err(Canceled)) => Poll::Ready(Err(ErrorKind::Disconnected.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
 }
 
-#[async]
-fn message_receiver<R>(mut reader: R, message_resolver: Rc<RefCell<MessageResolver>>) -> Result<()> where
-    R: AsyncRead + 'static,
+/// Sends the commands of the client and resolves the messages of the relay
+/// until either side closes the connection.
+async fn run<S>(stream: S, mut connection: Connection, mut command_rx: UnboundedReceiver<Command>, message_resolver: Arc<Mutex<MessageResolver>>) -> Result<()> where
+    S: AsyncRead + AsyncWrite,
 {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut buf = vec![0; 8192];
+    let handshake_deadline = connection.handshake_timeout().map(|timeout| Instant::now() + timeout);
+
     loop {
-        let (mut r, message) = match await!(raw::read_message(reader)) {
-            Ok(res) => res,
-            Err(err) => {
-                println!(">>> {:?}", err);
-                break
+        let outgoing = connection.take_outgoing();
+        if !outgoing.is_empty() {
+            writer.write_all(&outgoing).await?;
+            writer.flush().await?;
+        }
+
+        let read = reader.read(&mut buf);
+        pin_mut!(read);
+
+        // Old relays never respond to the handshake
+        let deadline = handshake_deadline.filter(|_| connection.handshake_timeout().is_some());
+        let handshake_timeout = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => future::pending().await,
             }
         };
+        pin_mut!(handshake_timeout);
 
-        if let Err(err) = message_resolver.borrow_mut().resolve(&mut message.as_slice()).chain_err(|| "resolving message") {
-            println!(">>> {}", err.display_chain());
-        };
+        match future::select(future::select(read, handshake_timeout), command_rx.next()).await {
+            Either::Left((Either::Left((len, _)), _)) => {
+                let len = len.chain_err(|| "reading message")?;
+
+                // The relay closed the connection
+                ensure!(len > 0, ErrorKind::Disconnected);
 
-        reader = r;
+                connection.feed(&buf[..len]);
+
+                while let Some(message) = connection.next_message().chain_err(|| "decoding message")? {
+                    if let Err(err) = message_resolver.lock().unwrap().resolve(message) {
+                        warn!(error = %err.display_chain(), "dropping message");
+                    }
+                }
+            },
+            Either::Left((Either::Right(((), _)), _)) => connection.skip_handshake()?,
+            // Commands are checked before they are queued, so this can't fail
+            Either::Right((Some(command), _)) => connection.send(command)?,
+            Either::Right((None, _)) => {
+                debug!("client dropped, closing connection");
+                return Ok(());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use crate::auth::Totp;
+    use crate::testing::{MockRelay,Response};
+    use crate::object::{Object,HData,HDataKey,HDataItem,Pointer};
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        let relay = MockRelay::new("secret").without_handshake();
+        let stream = TcpStream::connect(relay.listen().await.unwrap()).await.unwrap();
+        let options = AuthOptions::new("secret").handshake_timeout(Duration::from_millis(10));
+
+        let client = Client::auth(stream, options).await.unwrap();
+        assert_eq!(client.ping("hi").await.unwrap(), "hi");
+
+        let commands = relay.received().into_iter().map(|req| req.command).collect::<Vec<_>>();
+        assert_eq!(&commands[..2], &["handshake", "init"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_argument() {
+        let relay = MockRelay::new("secret");
+        let stream = TcpStream::connect(relay.listen().await.unwrap()).await.unwrap();
+        let client = Client::auth(stream, "secret").await.unwrap();
+
+        assert!(matches!(client.info("version\nquit").await, Err(Error(ErrorKind::InvalidArgument(_), _))));
+        assert!(client.ping("hi\nquit").await.is_err());
+        assert!(client.hdata("buffer:gui_buffers(*)").keys(&["name\nquit"]).await.is_err());
+        assert!(client.sync(&["core.weechat\nquit"], &[]).is_err());
+        assert!(client.input("core.weechat\nquit", "hi").is_err());
+
+        // Nothing was sent and the connection is still usable
+        assert_eq!(client.ping("hi").await.unwrap(), "hi");
+        assert!(relay.received().iter().all(|req| req.command != "quit" && req.command != "info"));
     }
 
-    // TODO: Allow canceling of existing promises
+    #[tokio::test]
+    async fn totp_rejected() {
+        let relay = MockRelay::new("secret");
+        let stream = TcpStream::connect(relay.listen().await.unwrap()).await.unwrap();
+        let options = AuthOptions::new("wrong").totp(Totp::Code("123456".into()));
 
-    println!("Quitting receiver :(");
-    bail!(ErrorKind::Disconnected)
+        // Only the password is wrong, but the relay doesn't tell which one it rejected
+        match Client::auth(stream, options).await {
+            Err(Error(ErrorKind::TotpRejected, _)) => {},
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("authenticated with a wrong password"),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_backlog() {
+        let line = |pointer: usize, message: &str| HDataItem::new(
+            vec![Pointer::from(0x1), Pointer::from(0x2), Pointer::from(pointer), Pointer::from(pointer)],
+            hashmap!{
+                Object::str("date") => Object::time(1000u64),
+                Object::str("date_printed") => Object::time(1000u64),
+                Object::str("displayed") => Object::char(1),
+                Object::str("highlight") => Object::char(0),
+                Object::str("tags_array") => Object::array(Vec::new()),
+                Object::str("prefix") => Object::str("nick"),
+                Object::str("message") => Object::str(message),
+            },
+        );
+        let relay = MockRelay::new("secret")
+            .on("hdata", move |req| vec![Response::reply(req, vec![Object::hdata(HData::new(
+                "buffer/lines/line/line_data",
+                vec![
+                    HDataKey::new("date", b"tim"), HDataKey::new("date_printed", b"tim"),
+                    HDataKey::new("displayed", b"chr"), HDataKey::new("highlight", b"chr"),
+                    HDataKey::new("tags_array", b"arr"), HDataKey::new("prefix", b"str"), HDataKey::new("message", b"str"),
+                ],
+                vec![line(0x11, "newer"), line(0x10, "older")],
+            ))])]);
+        let stream = TcpStream::connect(relay.listen().await.unwrap()).await.unwrap();
+        let client = Client::auth(stream, "secret").await.unwrap();
+
+        let lines = client.fetch_backlog(&Pointer::from(0x1), 2).await.unwrap();
+
+        assert_eq!(lines.iter().map(|line| line.message()).collect::<Vec<_>>(), vec!["older", "newer"]);
+        assert_eq!(lines[0].buffer(), &Pointer::from(0x1));
+        assert!(relay.received().last().unwrap().args.starts_with("buffer:0x1/own_lines/last_line(-2)/data date,"));
+    }
+
+    #[tokio::test]
+    async fn fetch_hotlist() {
+        use crate::hotlist::Priority;
+
+        let relay = MockRelay::new("secret")
+            .on("hdata", |req| vec![Response::reply(req, vec![Object::hdata(HData::new(
+                "hotlist",
+                vec![HDataKey::new("priority", b"int"), HDataKey::new("buffer", b"ptr"), HDataKey::new("count", b"arr")],
+                vec![HDataItem::new(vec![Pointer::from(0x10)], hashmap!{
+                    Object::str("priority") => Object::int(1),
+                    Object::str("buffer") => Object::pointer(0x1),
+                    Object::str("count") => Object::array(vec![Object::int(0), Object::int(3), Object::int(0), Object::int(0)]),
+                })],
+            ))])]);
+        let stream = TcpStream::connect(relay.listen().await.unwrap()).await.unwrap();
+        let client = Client::auth(stream, "secret").await.unwrap();
+
+        let hotlist = client.fetch_hotlist().await.unwrap();
+        assert_eq!(hotlist.count(&Pointer::from(0x1), Priority::Message), 3);
+
+        client.mark_read(Pointer::from(0x1)).unwrap();
+        client.ping("done").await.unwrap();
+
+        let received = relay.received();
+        assert_eq!(received.iter().filter(|req| req.command == "input").map(|req| req.args.as_str()).collect::<Vec<_>>(), vec![
+            "0x1 /buffer set hotlist -1",
+            "0x1 /input set_unread_current_buffer",
+        ]);
+    }
 }
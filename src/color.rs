@@ -0,0 +1,553 @@
+//! Parsing of the color codes in strings sent by WeeChat, e.g. in messages and prefixes.
+//!
+//! ```ignore
+//! for span in color::parse(line.message()) {
+//!     print(span.text(), span.style().fg());
+//! }
+//! ```
+
+use std::convert::TryFrom;
+
+const COLOR: u8 = 0x19;
+const SET_ATTRIBUTE: u8 = 0x1A;
+const REMOVE_ATTRIBUTE: u8 = 0x1B;
+const RESET: u8 = 0x1C;
+/// Bold, reverse, italic, underline, blink and dim as control characters.
+const ATTRIBUTES: std::ops::RangeInclusive<u8> = 0x01..=0x06;
+
+/// A foreground or background color.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash,Default)]
+pub enum Color {
+    /// The default color of the terminal.
+    #[default]
+    Default,
+    /// One of the 16 colors every terminal has.
+    Basic(BasicColor),
+    /// One of the 256 colors of terminals that support them.
+    Extended(u8),
+    /// The color of a WeeChat color option, e.g. `weechat.color.chat_nick`.
+    ///
+    /// The numbers are the ones of `gui_color_enum` in `gui-color.h` and depend on the WeeChat version.
+    Option(u8),
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum BasicColor {
+    Black,
+    DarkGray,
+    Red,
+    LightRed,
+    Green,
+    LightGreen,
+    Brown,
+    Yellow,
+    Blue,
+    LightBlue,
+    Magenta,
+    LightMagenta,
+    Cyan,
+    LightCyan,
+    Gray,
+    White,
+}
+
+impl BasicColor {
+    /// The name of the color in WeeChat, e.g. `lightred`.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BasicColor::Black        => "black",
+            BasicColor::DarkGray     => "darkgray",
+            BasicColor::Red          => "red",
+            BasicColor::LightRed     => "lightred",
+            BasicColor::Green        => "green",
+            BasicColor::LightGreen   => "lightgreen",
+            BasicColor::Brown        => "brown",
+            BasicColor::Yellow       => "yellow",
+            BasicColor::Blue         => "blue",
+            BasicColor::LightBlue    => "lightblue",
+            BasicColor::Magenta      => "magenta",
+            BasicColor::LightMagenta => "lightmagenta",
+            BasicColor::Cyan         => "cyan",
+            BasicColor::LightCyan    => "lightcyan",
+            BasicColor::Gray         => "gray",
+            BasicColor::White        => "white",
+        }
+    }
+
+    /// Returns the color with the number WeeChat uses in color codes, from 1 to 16.
+    fn from_number(number: u32) -> Option<Self> {
+        Some(match number {
+            1  => BasicColor::Black,
+            2  => BasicColor::DarkGray,
+            3  => BasicColor::Red,
+            4  => BasicColor::LightRed,
+            5  => BasicColor::Green,
+            6  => BasicColor::LightGreen,
+            7  => BasicColor::Brown,
+            8  => BasicColor::Yellow,
+            9  => BasicColor::Blue,
+            10 => BasicColor::LightBlue,
+            11 => BasicColor::Magenta,
+            12 => BasicColor::LightMagenta,
+            13 => BasicColor::Cyan,
+            14 => BasicColor::LightCyan,
+            15 => BasicColor::Gray,
+            16 => BasicColor::White,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Attribute {
+    Bold,
+    Reverse,
+    Italic,
+    Underline,
+}
+
+impl Attribute {
+    /// Returns the attribute of a symbol, as used within color codes.
+    fn from_symbol(symbol: u8) -> Option<Self> {
+        Some(match symbol {
+            b'*' => Attribute::Bold,
+            b'!' => Attribute::Reverse,
+            b'/' => Attribute::Italic,
+            b'_' => Attribute::Underline,
+            _ => return None,
+        })
+    }
+}
+
+/// The attributes of a span of text.
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct Attributes {
+    bold: bool,
+    reverse: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Attributes {
+    pub fn bold(&self) -> bool {
+        self.bold
+    }
+
+    /// Foreground and background are swapped.
+    pub fn reverse(&self) -> bool {
+        self.reverse
+    }
+
+    pub fn italic(&self) -> bool {
+        self.italic
+    }
+
+    pub fn underline(&self) -> bool {
+        self.underline
+    }
+
+    pub fn contains(&self, attribute: Attribute) -> bool {
+        match attribute {
+            Attribute::Bold      => self.bold,
+            Attribute::Reverse   => self.reverse,
+            Attribute::Italic    => self.italic,
+            Attribute::Underline => self.underline,
+        }
+    }
+
+    fn set(&mut self, attribute: Attribute, value: bool) {
+        match attribute {
+            Attribute::Bold      => self.bold = value,
+            Attribute::Reverse   => self.reverse = value,
+            Attribute::Italic    => self.italic = value,
+            Attribute::Underline => self.underline = value,
+        }
+    }
+
+    fn insert(&mut self, other: Attributes) {
+        self.bold |= other.bold;
+        self.reverse |= other.reverse;
+        self.italic |= other.italic;
+        self.underline |= other.underline;
+    }
+}
+
+/// How a span of text is displayed.
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct Style {
+    fg: Color,
+    bg: Color,
+    attributes: Attributes,
+    emphasis: bool,
+}
+
+impl Style {
+    pub fn fg(&self) -> Color {
+        self.fg
+    }
+
+    pub fn bg(&self) -> Color {
+        self.bg
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
+
+    /// The text is emphasized, e.g. because it matches a search.
+    pub fn emphasis(&self) -> bool {
+        self.emphasis
+    }
+}
+
+/// A part of a string displayed with the same style.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Span<'a> {
+    text: &'a str,
+    style: Style,
+}
+
+impl<'a> Span<'a> {
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+}
+
+/// Splits `text` into spans at its color codes.
+///
+/// Spans never contain color codes, attribute control characters or are empty.
+/// Invalid codes are skipped like WeeChat does.
+pub fn parse(text: &str) -> Vec<Span<'_>> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        style: Style::default(),
+    };
+    let mut spans = Vec::new();
+
+    while parser.pos < text.len() {
+        let start = parser.pos;
+        let end = text.as_bytes()[start..].iter()
+            .position(|byte| (COLOR..=RESET).contains(byte) || ATTRIBUTES.contains(byte))
+            .map_or(text.len(), |len| start + len);
+
+        if end > start {
+            spans.push(Span {
+                text: &text[start..end],
+                style: parser.style,
+            });
+        }
+
+        parser.pos = end;
+        parser.code();
+    }
+
+    spans
+}
+
+/// Removes all color codes from `text`.
+pub fn strip(text: &str) -> String {
+    parse(text).iter().map(Span::text).collect()
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    style: Style,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// Parses the code at the current position, if any.
+    fn code(&mut self) {
+        match self.next() {
+            Some(COLOR) => self.color_code(),
+            Some(SET_ATTRIBUTE) => {
+                if let Some(attribute) = self.attribute() {
+                    self.style.attributes.set(attribute, true);
+                }
+            },
+            Some(REMOVE_ATTRIBUTE) => {
+                if let Some(attribute) = self.attribute() {
+                    self.style.attributes.set(attribute, false);
+                }
+            },
+            Some(RESET) => self.style = Style::default(),
+            _ => {},
+        }
+    }
+
+    /// Parses what follows `0x19`.
+    fn color_code(&mut self) {
+        match self.peek() {
+            Some(b'F') => {
+                self.pos += 1;
+                let (attributes, keep) = self.attributes();
+                if let Some(fg) = self.color() {
+                    self.set_fg(fg, attributes, keep);
+                }
+            },
+            Some(b'B') => {
+                self.pos += 1;
+                if let Some(bg) = self.color() {
+                    self.style.bg = bg;
+                }
+            },
+            Some(b'*') => {
+                self.pos += 1;
+                let (attributes, keep) = self.attributes();
+                if let Some(fg) = self.color() {
+                    self.set_fg(fg, attributes, keep);
+
+                    // Newer versions separate the background with `~`
+                    if let Some(b',') | Some(b'~') = self.peek() {
+                        let start = self.pos;
+                        self.pos += 1;
+
+                        match self.color() {
+                            Some(bg) => self.style.bg = bg,
+                            None => self.pos = start,
+                        }
+                    }
+                }
+            },
+            Some(b'E') => {
+                self.pos += 1;
+                self.style.emphasis = true;
+            },
+            Some(b'e') => {
+                self.pos += 1;
+                self.style.emphasis = false;
+            },
+            Some(RESET) => {
+                self.pos += 1;
+                self.style.fg = Color::Default;
+                self.style.bg = Color::Default;
+            },
+            // A color pair, only used by the buffer of `/color`
+            Some(b'@') => {
+                self.pos += 1;
+                self.digits(5);
+            },
+            // Bar colors and markers of the input bar, rarely sent for lines
+            Some(b'b') => {
+                self.pos += 1;
+                self.bar_code();
+            },
+            Some(byte) if byte.is_ascii_digit() => {
+                if let Some(option) = self.digits(2) {
+                    self.style.fg = Color::Option(option as u8);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Parses what follows `0x19b`.
+    ///
+    /// The foreground, delimiter and background colors of bars are applied like `F` and `B`.
+    /// The markers of the input bar have no arguments.
+    fn bar_code(&mut self) {
+        match self.peek() {
+            Some(b'F') | Some(b'D') => {
+                self.pos += 1;
+                let (attributes, keep) = self.attributes();
+                if let Some(fg) = self.color() {
+                    self.set_fg(fg, attributes, keep);
+                }
+            },
+            Some(b'B') => {
+                self.pos += 1;
+                if let Some(bg) = self.color() {
+                    self.style.bg = bg;
+                }
+            },
+            Some(byte) if byte.is_ascii() => self.pos += 1,
+            _ => {},
+        }
+    }
+
+    /// Parses the attribute symbol following `0x1A` or `0x1B`, keeping anything else as text.
+    fn attribute(&mut self) -> Option<Attribute> {
+        match self.peek()? {
+            // Blink and dim, which terminal clients rarely support
+            b'%' | b'.' => {
+                self.pos += 1;
+                None
+            },
+            symbol => {
+                let attribute = Attribute::from_symbol(symbol);
+                if attribute.is_some() {
+                    self.pos += 1;
+                }
+
+                attribute
+            },
+        }
+    }
+
+    /// Parses the attributes in front of a foreground color
+    /// and whether the current attributes are kept.
+    fn attributes(&mut self) -> (Attributes, bool) {
+        let mut attributes = Attributes::default();
+        let mut keep = false;
+
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'|' => keep = true,
+                // Blink and dim, which terminal clients rarely support
+                b'%' | b'.' => {},
+                byte => match Attribute::from_symbol(byte) {
+                    Some(attribute) => attributes.set(attribute, true),
+                    None => break,
+                },
+            }
+
+            self.pos += 1;
+        }
+
+        (attributes, keep)
+    }
+
+    /// Parses a basic color as 2 digits or an extended color as `@` and 5 digits.
+    fn color(&mut self) -> Option<Color> {
+        if self.peek() == Some(b'@') {
+            let start = self.pos;
+            self.pos += 1;
+
+            let color = self.digits(5).and_then(|color| u8::try_from(color).ok()).map(Color::Extended);
+            if color.is_none() {
+                self.pos = start;
+            }
+
+            return color;
+        }
+
+        match self.digits(2)? {
+            0 => Some(Color::Default),
+            color => BasicColor::from_number(color).map(Color::Basic),
+        }
+    }
+
+    fn digits(&mut self, len: usize) -> Option<u32> {
+        let digits = self.text.as_bytes().get(self.pos..self.pos + len)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        self.pos += len;
+        Some(digits.iter().fold(0, |number, digit| number * 10 + u32::from(digit - b'0')))
+    }
+
+    /// Setting the foreground resets the attributes unless they are kept.
+    fn set_fg(&mut self, fg: Color, attributes: Attributes, keep: bool) {
+        if !keep {
+            self.style.attributes = Attributes::default();
+        }
+
+        self.style.attributes.insert(attributes);
+        self.style.fg = fg;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(text: &str) -> Vec<&str> {
+        parse(text).iter().map(Span::text).collect()
+    }
+
+    #[test]
+    fn plain() {
+        assert!(parse("").is_empty());
+        assert_eq!(texts("hello"), vec!["hello"]);
+        assert_eq!(parse("hello")[0].style(), Style::default());
+    }
+
+    #[test]
+    fn colors() {
+        let spans = parse("\x19F03red\x19B@00017on blue\x19*@00214,05both\x19\x1Cdefault");
+
+        assert_eq!(spans.iter().map(Span::text).collect::<Vec<_>>(), vec!["red", "on blue", "both", "default"]);
+        assert_eq!(spans[0].style().fg(), Color::Basic(BasicColor::Red));
+        assert_eq!(spans[1].style().bg(), Color::Extended(17));
+        assert_eq!(spans[2].style().fg(), Color::Extended(214));
+        assert_eq!(spans[2].style().bg(), Color::Basic(BasicColor::Green));
+        assert_eq!(spans[3].style(), Style::default());
+
+        let spans = parse("\x1928nick\x19F00text");
+        assert_eq!(spans[0].style().fg(), Color::Option(28));
+        assert_eq!(spans[1].style().fg(), Color::Default);
+    }
+
+    #[test]
+    fn attributes() {
+        let spans = parse("\x1A*bold\x19F*/05bold italic\x19F|_06kept\x19F07reset\x1A_\x1B_plain\x19Eemphasis");
+
+        assert!(spans[0].style().attributes().bold());
+        let attributes = spans[1].style().attributes();
+        assert!(attributes.bold() && attributes.italic() && !attributes.underline());
+        let attributes = spans[2].style().attributes();
+        assert!(attributes.bold() && attributes.italic() && attributes.underline());
+        assert_eq!(spans[3].style().attributes(), Attributes::default());
+        assert_eq!(spans[4].text(), "plain");
+        assert_eq!(spans[4].style().attributes(), Attributes::default());
+        assert!(spans[5].style().emphasis());
+    }
+
+    #[test]
+    fn invalid_codes() {
+        // The separator is kept if no background follows
+        assert_eq!(texts("\x19*05,text"), vec![",text"]);
+        assert_eq!(texts("\x19Fxx\x19Btext\x19"), vec!["xx", "text"]);
+        assert_eq!(texts("é\x19F05ü\x1Aä\x19bö"), vec!["é", "ü", "ä", "ö"]);
+    }
+
+    #[test]
+    fn strip_codes() {
+        assert_eq!(strip("\x19F*05nick\x1C: \x1A*hi\x1B* \x19bF05there\x19bD@00214\x19bB01\x19b_!"), "nick: hi there!");
+        assert_eq!(strip("\x19*05hello"), "hello");
+        assert_eq!(strip("\x19*05éa"), "éa");
+        assert_eq!(strip("\x19*05~é"), "~é");
+    }
+
+    #[test]
+    fn attribute_symbols() {
+        // Only symbols follow 0x1A and 0x1B, control characters aren't attributes there
+        let spans = parse("\x1A!reverse\x1B!\x1A\x02plain\x1A%blink");
+        assert_eq!(spans.iter().map(Span::text).collect::<Vec<_>>(), vec!["reverse", "plain", "blink"]);
+        assert!(spans[0].style().attributes().reverse());
+        assert_eq!(spans[1].style().attributes(), Attributes::default());
+        assert_eq!(spans[2].style().attributes(), Attributes::default());
+        assert_eq!(texts("\x1Aa\x1B|b"), vec!["a", "|b"]);
+    }
+
+    #[test]
+    fn strip_control_characters() {
+        assert_eq!(strip("\x01bold\x02\x03\x04 \x05blink\x06dim"), "bold blinkdim");
+        assert_eq!(strip("\x1A\x05\x1B\x06text"), "text");
+    }
+
+    #[test]
+    fn bar_colors() {
+        let spans = parse("\x19bF*03bar\x19bB@00017text");
+
+        assert_eq!(spans[0].style().fg(), Color::Basic(BasicColor::Red));
+        assert!(spans[0].style().attributes().bold());
+        assert_eq!(spans[1].style().bg(), Color::Extended(17));
+    }
+}
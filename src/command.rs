@@ -1,10 +1,20 @@
-use futures::prelude::*;
-use tokio_io::io;
-use tokio_io::AsyncWrite;
-use Result;
+use std::io::{self,Write};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use crate::object::Pointer;
+use crate::errors::*;
 
-pub trait IdGen {
-    fn gen_id(&mut self) -> String;
+/// Generates the ids of requests on a connection.
+///
+/// Clones share the counter, so ids stay unique no matter which clone generated them.
+#[derive(Clone,Debug,Default)]
+pub(crate) struct RequestIds(Arc<AtomicUsize>);
+
+impl RequestIds {
+    pub fn next(&self) -> String {
+        (self.0.fetch_add(1, Ordering::Relaxed) + 1).to_string()
+    }
 }
 
 #[derive(Clone,Debug)]
@@ -12,32 +22,54 @@ pub enum Command {
     Ping(Ping),
     Info(Info),
     InfoList(InfoList),
+    HData(HData),
+    Sync(Sync),
+    Desync(Desync),
+    Input(Input),
+    Completion(Completion),
+    Nicklist(Nicklist),
 }
 
 impl Command {
-    #[async]
-    pub(crate) fn send_raw<W: AsyncWrite + 'static>(self, writer: W) -> Result<W> {
-        let writer = match self {
-            Command::Ping(c)     => await!(c.send_raw(writer))?,
-            Command::Info(c)     => await!(c.send_raw(writer))?,
-            Command::InfoList(c) => await!(c.send_raw(writer))?,
-        };
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        match self {
+            Command::Ping(c)     => c.encode(w),
+            Command::Info(c)     => c.encode(w),
+            Command::InfoList(c) => c.encode(w),
+            Command::HData(c)    => c.encode(w),
+            Command::Sync(c)     => c.encode(w),
+            Command::Desync(c)   => c.encode(w),
+            Command::Input(c)    => c.encode(w),
+            Command::Completion(c) => c.encode(w),
+            Command::Nicklist(c) => c.encode(w),
+        }
+    }
 
-        Ok(writer)
+    /// Fails if the command can't be encoded, e.g. because an argument contains a line break.
+    pub(crate) fn check(&self) -> Result<()> {
+        self.clone().encode(&mut io::sink())
     }
 }
 
+/// Fails if `arg` would not be sent as a single argument.
+///
+/// Every encoder checks its arguments with this before writing anything.
+fn check_arg(arg: &str) -> Result<()> {
+    ensure!(!arg.contains(['\n', '\r']), ErrorKind::InvalidArgument(arg.into()));
+    Ok(())
+}
+
 #[derive(Clone,Debug)]
 pub struct Ping(pub String);
 
 impl Ping {
-    #[async]
-    pub(crate) fn send_raw<W: AsyncWrite + 'static>(self, writer: W) -> Result<W> {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
         let Ping(msg) = self;
+        check_arg(&msg)?;
         let data = format!("ping {}\n", msg);
-        let (writer, _) = await!(io::write_all(writer, data))?;
+        w.write_all(data.as_bytes())?;
 
-        Ok(writer)
+        Ok(())
     }
 }
 
@@ -51,13 +83,20 @@ impl From<Ping> for Command {
 pub struct Info(pub String, pub String);
 
 impl Info {
-    #[async]
-    pub(crate) fn send_raw<W: AsyncWrite + 'static>(self, writer: W) -> Result<W> {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
         let Info(id, name) = self;
+        check_arg(&id)?;
+        check_arg(&name)?;
         let data = format!("({}) info {}\n", id, name);
-        let (writer, _) = await!(io::write_all(writer, data))?;
+        w.write_all(data.as_bytes())?;
 
-        Ok(writer)
+        Ok(())
+    }
+}
+
+impl From<Info> for Command {
+    fn from(c: Info) -> Self {
+        Command::Info(c)
     }
 }
 
@@ -65,13 +104,14 @@ impl Info {
 pub struct InfoList(pub String, pub String);
 
 impl InfoList {
-    #[async]
-    pub(crate) fn send_raw<W: AsyncWrite + 'static>(self, writer: W) -> Result<W> {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
         let InfoList(id, name) = self;
+        check_arg(&id)?;
+        check_arg(&name)?;
         let data = format!("({}) infolist {}\n", id, name);
-        let (writer, _) = await!(io::write_all(writer, data))?;
+        w.write_all(data.as_bytes())?;
 
-        Ok(writer)
+        Ok(())
     }
 }
 
@@ -80,3 +120,309 @@ impl From<InfoList> for Command {
         Command::InfoList(c)
     }
 }
+
+#[derive(Clone,Debug)]
+pub struct HData(pub String, pub String, pub Vec<String>);
+
+impl HData {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let HData(id, path, keys) = self;
+        check_arg(&id)?;
+        check_arg(&path)?;
+        keys.iter().try_for_each(|key| check_arg(key))?;
+        let data = if keys.is_empty() {
+            format!("({}) hdata {}\n", id, path)
+        } else {
+            format!("({}) hdata {} {}\n", id, path, keys.join(","))
+        };
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<HData> for Command {
+    fn from(c: HData) -> Self {
+        Command::HData(c)
+    }
+}
+
+/// What to receive events for when syncing buffers.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum SyncOption {
+    /// Buffers being opened, closed, moved, renamed, etc.
+    Buffers,
+    /// WeeChat being upgraded.
+    Upgrade,
+    /// Lines and other changes in the buffers.
+    Buffer,
+    /// Changes to the nicklists of the buffers.
+    Nicklist,
+}
+
+impl SyncOption {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SyncOption::Buffers  => "buffers",
+            SyncOption::Upgrade  => "upgrade",
+            SyncOption::Buffer   => "buffer",
+            SyncOption::Nicklist => "nicklist",
+        }
+    }
+}
+
+fn sync_args(buffers: &[String], options: &[SyncOption]) -> Result<String> {
+    buffers.iter().try_for_each(|buffer| check_arg(buffer))?;
+    let mut args = String::new();
+
+    if !buffers.is_empty() || !options.is_empty() {
+        args.push(' ');
+        args.push_str(&if buffers.is_empty() { "*".into() } else { buffers.join(",") });
+    }
+
+    if !options.is_empty() {
+        let options = options.iter().map(SyncOption::as_str).collect::<Vec<_>>();
+        args.push(' ');
+        args.push_str(&options.join(","));
+    }
+
+    Ok(args)
+}
+
+#[derive(Clone,Debug)]
+pub struct Sync(pub Vec<String>, pub Vec<SyncOption>);
+
+impl Sync {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let Sync(buffers, options) = self;
+        let data = format!("sync{}\n", sync_args(&buffers, &options)?);
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<Sync> for Command {
+    fn from(c: Sync) -> Self {
+        Command::Sync(c)
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Desync(pub Vec<String>, pub Vec<SyncOption>);
+
+impl Desync {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let Desync(buffers, options) = self;
+        let data = format!("desync{}\n", sync_args(&buffers, &options)?);
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<Desync> for Command {
+    fn from(c: Desync) -> Self {
+        Command::Desync(c)
+    }
+}
+
+/// Identifies a buffer either by its full name (e.g. `irc.libera.#rust`) or by its pointer.
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum BufferId {
+    Name(String),
+    Pointer(Pointer),
+}
+
+impl fmt::Display for BufferId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BufferId::Name(ref name) => name.fmt(f),
+            BufferId::Pointer(ref ptr) => ptr.fmt(f),
+        }
+    }
+}
+
+impl From<&str> for BufferId {
+    fn from(name: &str) -> Self {
+        BufferId::Name(name.into())
+    }
+}
+
+impl From<String> for BufferId {
+    fn from(name: String) -> Self {
+        BufferId::Name(name)
+    }
+}
+
+impl From<Pointer> for BufferId {
+    fn from(ptr: Pointer) -> Self {
+        BufferId::Pointer(ptr)
+    }
+}
+
+impl From<&Pointer> for BufferId {
+    fn from(ptr: &Pointer) -> Self {
+        BufferId::Pointer(ptr.clone())
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Input(pub BufferId, pub String);
+
+impl Input {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let Input(buffer, text) = self;
+        check_arg(&buffer.to_string())?;
+        // Every line has to be sent as its own command,
+        // otherwise the rest of the text would be parsed as new commands.
+        // Empty lines would send an empty input, so they are skipped.
+        // `lines` also removes the `\r` of `\r\n`.
+        let data = text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("input {} {}\n", buffer, line))
+            .collect::<String>();
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<Input> for Command {
+    fn from(c: Input) -> Self {
+        Command::Input(c)
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Completion(pub String, pub BufferId, pub i32, pub String);
+
+impl Completion {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let Completion(id, buffer, position, data) = self;
+        check_arg(&id)?;
+        check_arg(&buffer.to_string())?;
+        let data = data.replace(['\n', '\r'], " ");
+        let data = if data.is_empty() {
+            format!("({}) completion {} {}\n", id, buffer, position)
+        } else {
+            format!("({}) completion {} {} {}\n", id, buffer, position, data)
+        };
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<Completion> for Command {
+    fn from(c: Completion) -> Self {
+        Command::Completion(c)
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Nicklist(pub String, pub Option<BufferId>);
+
+impl Nicklist {
+    pub(crate) fn encode<W: Write + ?Sized>(self, w: &mut W) -> Result<()> {
+        let Nicklist(id, buffer) = self;
+        check_arg(&id)?;
+        if let Some(ref buffer) = buffer {
+            check_arg(&buffer.to_string())?;
+        }
+        let data = match buffer {
+            Some(buffer) => format!("({}) nicklist {}\n", id, buffer),
+            None => format!("({}) nicklist\n", id),
+        };
+        w.write_all(data.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl From<Nicklist> for Command {
+    fn from(c: Nicklist) -> Self {
+        Command::Nicklist(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<C: Into<Command>>(command: C) -> String {
+        let mut data = Vec::new();
+        command.into().encode(&mut data).unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn encode_sync() {
+        let buffers = vec!["irc.libera.#rust".to_owned(), "irc.libera.#weechat".to_owned()];
+
+        assert_eq!(encode(Sync(Vec::new(), Vec::new())), "sync\n");
+        assert_eq!(encode(Sync(Vec::new(), vec![SyncOption::Buffers])), "sync * buffers\n");
+        assert_eq!(encode(Sync(buffers.clone(), Vec::new())), "sync irc.libera.#rust,irc.libera.#weechat\n");
+        assert_eq!(
+            encode(Desync(buffers, vec![SyncOption::Buffer, SyncOption::Nicklist])),
+            "desync irc.libera.#rust,irc.libera.#weechat buffer,nicklist\n",
+        );
+
+        for &(option, name) in &[
+            (SyncOption::Buffers, "buffers"),
+            (SyncOption::Upgrade, "upgrade"),
+            (SyncOption::Buffer, "buffer"),
+            (SyncOption::Nicklist, "nicklist"),
+        ] {
+            assert_eq!(encode(Sync(vec!["core.weechat".into()], vec![option])), format!("sync core.weechat {}\n", name));
+        }
+    }
+
+    #[test]
+    fn encode_input() {
+        let input = |text: &str| encode(Input(BufferId::Name("core.weechat".into()), text.into()));
+
+        assert_eq!(input("a\nb"), "input core.weechat a\ninput core.weechat b\n");
+        assert_eq!(input("a\r\n\nb"), "input core.weechat a\ninput core.weechat b\n");
+        assert_eq!(input(""), "");
+        assert_eq!(
+            encode(Input(BufferId::Pointer(Pointer::from(0x1)), "/help".into())),
+            "input 0x1 /help\n",
+        );
+    }
+
+    #[test]
+    fn reject_line_breaks() {
+        let buffer = |name: &str| BufferId::Name(name.into());
+        let rejected = |command: Command| {
+            let mut data = Vec::new();
+            let result = command.encode(&mut data);
+            assert!(matches!(result, Err(Error(ErrorKind::InvalidArgument(_), _))), "{:?}", result.map(|_| ()));
+            assert!(data.is_empty());
+        };
+
+        rejected(Ping("hi\nquit".into()).into());
+        rejected(Info("1".into(), "version\nquit".into()).into());
+        rejected(InfoList("1".into(), "buffer\rquit".into()).into());
+        rejected(HData("1".into(), "buffer:gui_buffers(*)\nquit".into(), Vec::new()).into());
+        rejected(HData("1".into(), "buffer:gui_buffers(*)".into(), vec!["name\nquit".into()]).into());
+        rejected(Sync(vec!["core.weechat\nquit".into()], Vec::new()).into());
+        rejected(Desync(vec!["core.weechat\nquit".into()], Vec::new()).into());
+        rejected(Input(buffer("core.weechat\nquit"), "hi".into()).into());
+        rejected(Completion("1".into(), buffer("core.weechat\nquit"), 0, "/he".into()).into());
+        rejected(Nicklist("1".into(), Some(buffer("core.weechat\nquit"))).into());
+
+        // Only the text of an input and completion may contain line breaks
+        assert!(Command::from(Input(buffer("core.weechat"), "a\r\nb".into())).check().is_ok());
+        assert!(Command::from(Nicklist("1".into(), None)).check().is_ok());
+    }
+
+    #[test]
+    fn encode_completion() {
+        let buffer = BufferId::Name("core.weechat".into());
+
+        assert_eq!(encode(Completion("1".into(), buffer.clone(), -1, "/hel".into())), "(1) completion core.weechat -1 /hel\n");
+        assert_eq!(encode(Completion("2".into(), buffer.clone(), 3, "/he\nl".into())), "(2) completion core.weechat 3 /he l\n");
+        assert_eq!(encode(Completion("3".into(), buffer, 0, String::new())), "(3) completion core.weechat 0\n");
+    }
+}
@@ -0,0 +1,124 @@
+use std::convert::TryFrom;
+use crate::object::{HData,HDataItem};
+use crate::errors::*;
+
+/// The candidates for completing the input at a given position.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Completion {
+    context: Context,
+    base_word: String,
+    pos_start: i32,
+    pos_end: i32,
+    add_space: bool,
+    list: Vec<String>,
+}
+
+impl Completion {
+    pub fn context(&self) -> Context {
+        self.context
+    }
+
+    /// The word that is being completed.
+    pub fn base_word(&self) -> &str {
+        &self.base_word
+    }
+
+    /// Index of the first character to replace.
+    pub fn pos_start(&self) -> i32 {
+        self.pos_start
+    }
+
+    /// Index of the last character to replace.
+    pub fn pos_end(&self) -> i32 {
+        self.pos_end
+    }
+
+    /// Whether a space should be added after the completed word.
+    pub fn add_space(&self) -> bool {
+        self.add_space
+    }
+
+    /// The possible completions of the base word.
+    pub fn list(&self) -> &[String] {
+        &self.list
+    }
+
+    /// Decodes the response to a `completion` command,
+    /// which contains no item if nothing can be completed.
+    pub(crate) fn from_hdata(hdata: &HData) -> Result<Option<Self>> {
+        hdata.items().first().map(Completion::try_from).transpose()
+    }
+}
+
+impl TryFrom<&HDataItem> for Completion {
+    type Error = Error;
+
+    fn try_from(item: &HDataItem) -> Result<Self> {
+        let list = item.get_array("list")?
+            .iter()
+            .map(|word| word.as_str().map(String::from).ok_or_else(|| Error::from(ErrorKind::UnexpectedType)))
+            .collect::<Result<_>>()
+            .chain_err(|| "decoding completion list")?;
+
+        Ok(Completion {
+            context: Context::from(item.get_str("context")?),
+            base_word: item.get_str("base_word")?.into(),
+            pos_start: item.get_int("pos_start")?,
+            pos_end: item.get_int("pos_end")?,
+            add_space: item.get_int("add_space")? != 0,
+            list,
+        })
+    }
+}
+
+/// What kind of word is being completed.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Context {
+    /// Completion of text without a command.
+    Null,
+    /// Completion of a command name.
+    Command,
+    /// Completion of a command argument.
+    CommandArg,
+    /// Default completion.
+    Auto,
+}
+
+impl From<&str> for Context {
+    fn from(context: &str) -> Self {
+        match context {
+            "command" => Context::Command,
+            "command_arg" => Context::CommandArg,
+            "auto" => Context::Auto,
+            _ => Context::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Object,Pointer};
+
+    #[test]
+    fn decode() {
+        let item = HDataItem::new(vec![Pointer::from(0x1)], hashmap!{
+            Object::str("context") => Object::str("command_arg"),
+            Object::str("base_word") => Object::str("fo"),
+            Object::str("pos_start") => Object::int(6),
+            Object::str("pos_end") => Object::int(7),
+            Object::str("add_space") => Object::int(1),
+            Object::str("list") => Object::array(vec![Object::str("foo"), Object::str("foobar")]),
+        });
+
+        let completion = Completion::from_hdata(&HData::new("completion", Vec::new(), vec![item])).unwrap().unwrap();
+        assert_eq!(completion.context(), Context::CommandArg);
+        assert_eq!(completion.base_word(), "fo");
+        assert_eq!((completion.pos_start(), completion.pos_end()), (6, 7));
+        assert!(completion.add_space());
+        assert_eq!(completion.list(), &["foo".to_owned(), "foobar".to_owned()][..]);
+
+        // Nothing to complete
+        assert_eq!(Completion::from_hdata(&HData::new("completion", Vec::new(), Vec::new())).unwrap(), None);
+    }
+}
@@ -0,0 +1,441 @@
+//! The relay protocol without any I/O.
+//!
+//! A `Connection` turns requests into the bytes to send to the relay
+//! and the bytes received from the relay into messages.
+//! It doesn't depend on an executor, so it can be driven by any event loop,
+//! e.g. the one of a GUI toolkit. `Client` and `BlockingClient` are built on top of it.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::mem;
+use std::time::Duration;
+use error_chain::ChainedError;
+use crate::auth::{AuthOptions,Handshake};
+use crate::command::{self,Command,RequestIds,SyncOption,BufferId};
+use crate::completion::Completion;
+use crate::event::Event;
+use crate::message::{self,Id};
+use crate::nicklist::Nicklist;
+use crate::object::{self,HashTable,DecodableObject};
+use crate::raw;
+use crate::errors::*;
+
+/// The state of a connection to the relay, independent of how the bytes are transferred.
+///
+/// Requests only queue commands, which have to be taken with `take_outgoing` and sent to the relay.
+/// Bytes received from the relay are passed to `feed`, the decoded messages are taken with `next_message`.
+/// Requests fail with `ErrorKind::InvalidArgument` if an argument contains a line break.
+pub struct Connection {
+    state: State,
+    options: AuthOptions,
+    ids: RequestIds,
+    requests: HashMap<String, RequestKind>,
+    queued: Vec<Command>,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum State {
+    /// Waiting for the response to the `handshake` command.
+    Handshake,
+    /// Waiting for the pong confirming that `init` succeeded.
+    Init,
+    Authenticated,
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+enum RequestKind {
+    Info,
+    InfoList,
+    HData,
+    Completion,
+    Nicklist,
+}
+
+/// A message received from the relay.
+#[derive(Debug)]
+pub enum Message {
+    /// The relay accepted the credentials.
+    Authenticated,
+    /// The reply to `Connection::ping`.
+    Pong(String),
+    /// The response to the request with the given id, or why it couldn't be decoded.
+    Response(String, Result<Response>),
+    Event(Event),
+}
+
+/// The response to a request.
+#[derive(Debug)]
+pub enum Response {
+    Info(object::Info),
+    InfoList(object::InfoList),
+    HData(object::HData),
+    Completion(Option<Completion>),
+    Nicklist(Nicklist),
+}
+
+impl Connection {
+    /// Starts a connection authenticating with `options`.
+    ///
+    /// `options` can be a plain password or an `AuthOptions`.
+    /// Requests can be queued right away, they are sent once the handshake is done.
+    pub fn new<A: Into<AuthOptions>>(options: A) -> Result<Self> {
+        let mut connection = Connection {
+            state: State::Handshake,
+            options: options.into(),
+            ids: RequestIds::default(),
+            requests: HashMap::new(),
+            queued: Vec::new(),
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        };
+
+        if connection.options.uses_handshake() {
+            let handshake = raw::encode_handshake(
+                connection.options.allowed_password_hash_algos(),
+                connection.options.requested_compression(),
+            );
+            connection.outgoing.extend_from_slice(handshake.as_bytes());
+        } else {
+            connection.init(None)?;
+        }
+
+        Ok(connection)
+    }
+
+    /// Whether the relay accepted the credentials.
+    ///
+    /// The relay closes the connection instead if it rejects them.
+    pub fn is_authenticated(&self) -> bool {
+        self.state == State::Authenticated
+    }
+
+    /// How long to wait for the response to the handshake, if it's still awaited.
+    ///
+    /// Call `skip_handshake` once it elapsed without a response.
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        match self.state {
+            State::Handshake => Some(self.options.requested_handshake_timeout()),
+            _ => None,
+        }
+    }
+
+    /// Gives up on the handshake and authenticates with the plain password,
+    /// as relays older than WeeChat 3.5 never respond to it.
+    pub fn skip_handshake(&mut self) -> Result<()> {
+        if self.state != State::Handshake {
+            return Ok(());
+        }
+
+        debug!("no response to handshake, sending plain password");
+        self.init(None)
+    }
+
+    fn init(&mut self, handshake: Option<&Handshake>) -> Result<()> {
+        let args = self.options.init_args(handshake)?;
+        let compression = handshake
+            .map(Handshake::compression)
+            .unwrap_or(self.options.requested_compression());
+        self.outgoing.extend_from_slice(raw::encode_init(&args, compression).as_bytes());
+        self.state = State::Init;
+
+        // The relay doesn't reply to `init`, so the first pong confirms it
+        self.send(command::Ping("auth".into()).into())?;
+
+        for command in mem::take(&mut self.queued) {
+            self.send(command)?;
+        }
+
+        Ok(())
+    }
+
+    /// The source of the ids of requests on this connection,
+    /// for assigning ids to commands passed to `send`.
+    pub(crate) fn request_ids(&self) -> RequestIds {
+        self.ids.clone()
+    }
+
+    fn new_id(&mut self) -> String {
+        self.ids.next()
+    }
+
+    /// Queues `command`, remembering how to decode the response to it.
+    ///
+    /// Fails without queuing anything if `command` can't be encoded,
+    /// e.g. because an argument contains a line break.
+    pub(crate) fn send(&mut self, command: Command) -> Result<()> {
+        command.check()?;
+
+        if let Some((id, kind)) = request_kind(&command) {
+            self.requests.insert(id.clone(), kind);
+        }
+
+        if self.state == State::Handshake {
+            self.queued.push(command);
+            return Ok(());
+        }
+
+        trace!(?command, "sending command");
+        command.encode(&mut self.outgoing)
+    }
+
+    pub fn ping<S: Into<String>>(&mut self, msg: S) -> Result<()> {
+        self.send(command::Ping(msg.into()).into())
+    }
+
+    /// Requests the info `name` and returns the id of the response.
+    pub fn info<S: Into<String>>(&mut self, name: S) -> Result<String> {
+        let id = self.new_id();
+        self.send(command::Info(id.clone(), name.into()).into())?;
+        Ok(id)
+    }
+
+    /// Requests the infolist `name` and returns the id of the response.
+    pub fn infolist<S: Into<String>>(&mut self, name: S) -> Result<String> {
+        let id = self.new_id();
+        self.send(command::InfoList(id.clone(), name.into()).into())?;
+        Ok(id)
+    }
+
+    /// Requests the hdata at `path` and returns the id of the response.
+    ///
+    /// All keys are sent if `keys` is empty.
+    pub fn hdata<P: Into<String>, K: AsRef<str>>(&mut self, path: P, keys: &[K]) -> Result<String> {
+        let id = self.new_id();
+        let keys = keys.iter().map(|key| key.as_ref().into()).collect();
+        self.send(command::HData(id.clone(), path.into(), keys).into())?;
+        Ok(id)
+    }
+
+    /// Starts receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    pub fn sync<S: AsRef<str>>(&mut self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let buffers = buffers.iter().map(|buffer| buffer.as_ref().into()).collect();
+        self.send(command::Sync(buffers, options.to_vec()).into())
+    }
+
+    /// Stops receiving events for `buffers`, or for all buffers if `buffers` is empty.
+    pub fn desync<S: AsRef<str>>(&mut self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let buffers = buffers.iter().map(|buffer| buffer.as_ref().into()).collect();
+        self.send(command::Desync(buffers, options.to_vec()).into())
+    }
+
+    /// Sends `text` to `buffer` as if it was typed by the user.
+    ///
+    /// See `Client::input` for how multiple lines are sent.
+    pub fn input<B: Into<BufferId>, S: Into<String>>(&mut self, buffer: B, text: S) -> Result<()> {
+        self.send(command::Input(buffer.into(), text.into()).into())
+    }
+
+    /// Requests completion of `data` at `position` in `buffer` and returns the id of the response.
+    pub fn completion<B: Into<BufferId>, S: Into<String>>(&mut self, buffer: B, position: i32, data: S) -> Result<String> {
+        let id = self.new_id();
+        self.send(command::Completion(id.clone(), buffer.into(), position, data.into()).into())?;
+        Ok(id)
+    }
+
+    /// Requests the nicklist of `buffer`, or of all buffers if `buffer` is `None`,
+    /// and returns the id of the response.
+    pub fn nicklist(&mut self, buffer: Option<BufferId>) -> Result<String> {
+        let id = self.new_id();
+        self.send(command::Nicklist(id.clone(), buffer).into())?;
+        Ok(id)
+    }
+
+    /// Takes the bytes that have to be sent to the relay.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.outgoing)
+    }
+
+    /// Passes bytes received from the relay.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.incoming.extend_from_slice(data);
+    }
+
+    /// Decodes the next message from the bytes received so far.
+    ///
+    /// Returns `None` if more bytes have to be fed first.
+    /// Responses to unknown requests and events that can't be decoded are logged and skipped.
+    /// Any error is fatal and the connection has to be closed.
+    pub fn next_message(&mut self) -> Result<Option<Message>> {
+        loop {
+            let data = match raw::decode_message(&self.incoming)? {
+                Some((data, len)) => {
+                    self.incoming.drain(..len);
+                    data
+                },
+                None => return Ok(None),
+            };
+
+            if let Some(message) = self.handle(&mut data.as_slice())? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    fn handle<R: Read>(&mut self, r: &mut R) -> Result<Option<Message>> {
+        let id = Id::decode(r).chain_err(|| "decoding id")?;
+        trace!(?id, "received message");
+
+        Ok(match id {
+            Id::Other(ref id) if self.state == State::Handshake && id == "handshake" => {
+                let response = HashTable::decode(r).chain_err(|| "decoding handshake response")?;
+                let handshake = Handshake::try_from(response).chain_err(|| "invalid handshake response")?;
+                self.init(Some(&handshake))?;
+                None
+            },
+            Id::Pong => {
+                let pong = message::Pong::decode(r).chain_err(|| "decoding pong")?;
+
+                if self.state == State::Init {
+                    debug!("authenticated");
+                    self.state = State::Authenticated;
+                    Some(Message::Authenticated)
+                } else {
+                    Some(Message::Pong(pong.0.into()))
+                }
+            },
+            Id::Other(id) => match self.requests.remove(&id) {
+                Some(kind) => {
+                    let response = kind.decode(r);
+                    Some(Message::Response(id, response))
+                },
+                None => {
+                    warn!(id = id.as_str(), "dropping response to unknown request");
+                    None
+                },
+            },
+            id => match Event::decode(id, r) {
+                Ok(event) => Some(Message::Event(event)),
+                Err(err) => {
+                    warn!(error = %err.display_chain(), "dropping event");
+                    None
+                },
+            },
+        })
+    }
+}
+
+fn request_kind(command: &Command) -> Option<(&String, RequestKind)> {
+    Some(match *command {
+        Command::Info(command::Info(ref id, _)) => (id, RequestKind::Info),
+        Command::InfoList(command::InfoList(ref id, _)) => (id, RequestKind::InfoList),
+        Command::HData(command::HData(ref id, _, _)) => (id, RequestKind::HData),
+        Command::Completion(command::Completion(ref id, _, _, _)) => (id, RequestKind::Completion),
+        Command::Nicklist(command::Nicklist(ref id, _)) => (id, RequestKind::Nicklist),
+        _ => return None,
+    })
+}
+
+impl RequestKind {
+    fn decode<R: Read>(self, r: &mut R) -> Result<Response> {
+        Ok(match self {
+            RequestKind::Info => Response::Info(object::Info::decode(r).chain_err(|| "decoding Info")?),
+            RequestKind::InfoList => Response::InfoList(object::InfoList::decode(r).chain_err(|| "decoding InfoList")?),
+            RequestKind::HData => Response::HData(object::HData::decode(r).chain_err(|| "decoding HData")?),
+            RequestKind::Completion => {
+                let hdata = object::HData::decode(r).chain_err(|| "decoding HData")?;
+                Response::Completion(Completion::from_hdata(&hdata).chain_err(|| "decoding Completion")?)
+            },
+            RequestKind::Nicklist => {
+                let hdata = object::HData::decode(r).chain_err(|| "decoding HData")?;
+                Response::Nicklist(Nicklist::try_from(&hdata).chain_err(|| "decoding Nicklist")?)
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthOptions;
+    use crate::object::Object;
+    use crate::raw::{Compression,encode_message};
+
+    fn outgoing(connection: &mut Connection) -> String {
+        String::from_utf8(connection.take_outgoing()).unwrap()
+    }
+
+    #[test]
+    fn handshake() {
+        let mut connection = Connection::new(AuthOptions::new("secret")).unwrap();
+        let id = connection.info("version").unwrap();
+        assert!(outgoing(&mut connection).starts_with("(handshake) handshake "));
+
+        let response = encode_message("handshake", &[Object::HashTable(HashTable::from(hashmap!{
+            Object::str("password_hash_algo") => Object::str("plain"),
+            Object::str("password_hash_iterations") => Object::str("100000"),
+            Object::str("nonce") => Object::str("01ab"),
+            Object::str("compression") => Object::str("off"),
+        }))], Compression::Off).unwrap();
+
+        // The response may arrive in pieces
+        let (first, second) = response.split_at(7);
+        connection.feed(first);
+        assert!(connection.next_message().unwrap().is_none());
+        connection.feed(second);
+        assert!(connection.next_message().unwrap().is_none());
+
+        // Queued requests are sent after `init`
+        assert_eq!(
+            outgoing(&mut connection),
+            format!("init compression=off,password=secret\nping auth\n({}) info version\n", id),
+        );
+        assert!(!connection.is_authenticated());
+
+        connection.feed(&encode_message("_pong", &[Object::str("auth")], Compression::Off).unwrap());
+        assert!(matches!(connection.next_message().unwrap(), Some(Message::Authenticated)));
+        assert!(connection.is_authenticated());
+    }
+
+    #[test]
+    fn skip_handshake() {
+        let mut connection = Connection::new(AuthOptions::new("secret")).unwrap();
+        assert!(outgoing(&mut connection).starts_with("(handshake) handshake "));
+        assert!(connection.handshake_timeout().is_some());
+
+        connection.skip_handshake().unwrap();
+        assert_eq!(outgoing(&mut connection), "init compression=off,password=secret\nping auth\n");
+        assert!(connection.handshake_timeout().is_none());
+    }
+
+    #[test]
+    fn request_ids() {
+        let mut connection = Connection::new(AuthOptions::new("secret").handshake(false)).unwrap();
+        let ids = connection.request_ids();
+
+        assert_eq!(connection.info("version").unwrap(), "1");
+        assert_eq!(ids.next(), "2");
+        assert_eq!(connection.infolist("buffer").unwrap(), "3");
+    }
+
+    #[test]
+    fn responses() {
+        let mut connection = Connection::new(AuthOptions::new("secret").handshake(false)).unwrap();
+        let id = connection.info("version").unwrap();
+        connection.ping("hi").unwrap();
+        assert_eq!(
+            outgoing(&mut connection),
+            format!("init compression=off,password=secret\nping auth\n({}) info version\nping hi\n", id),
+        );
+
+        let mut data = Vec::new();
+        data.extend(encode_message("_pong", &[Object::str("auth")], Compression::Off).unwrap());
+        data.extend(encode_message("unknown", &[Object::int(1)], Compression::Off).unwrap());
+        data.extend(encode_message(&id, &[Object::info(("version", "4.0.0"))], Compression::Zlib).unwrap());
+        data.extend(encode_message("_pong", &[Object::str("hi")], Compression::Off).unwrap());
+        connection.feed(&data);
+
+        assert!(matches!(connection.next_message().unwrap(), Some(Message::Authenticated)));
+        match connection.next_message().unwrap() {
+            Some(Message::Response(ref actual, Ok(Response::Info(ref info)))) => {
+                assert_eq!(*actual, id);
+                assert_eq!(info.value(), "4.0.0");
+            },
+            message => panic!("unexpected message {:?}", message),
+        }
+        assert!(match connection.next_message().unwrap() { Some(Message::Pong(ref pong)) => pong == "hi", _ => false });
+        assert!(connection.next_message().unwrap().is_none());
+    }
+}
@@ -4,14 +4,31 @@ error_chain! {
         UnknownCompression(code: u8)
         InvalidMessageLength
         Disconnected
-        AuthFailed
+        AuthFailed {
+            description("authentication failed")
+            display("the relay rejected the password")
+        }
+        TotpRequired
+        // The relay just closes the connection, so it's unknown whether the password or the TOTP was wrong
+        TotpRejected {
+            description("authentication with TOTP failed")
+            display("the relay rejected the password or the TOTP")
+        }
+        UnsupportedPasswordHashAlgo(algo: String)
         UnexpectedType
+        MissingKey(key: String)
         Decoding
         UnknownTag(tag: [u8; 3])
+        // A line break would end the command and start a new one
+        InvalidArgument(arg: String) {
+            description("invalid command argument")
+            display("command argument contains a line break: {:?}", arg)
+        }
         MissingResponsePromise
     }
 
     foreign_links {
         Io(::std::io::Error);
+        WebSocket(::tokio_tungstenite::tungstenite::Error) #[cfg(feature = "websocket")];
     }
 }
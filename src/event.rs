@@ -0,0 +1,57 @@
+use std::io::Read;
+use crate::message::Id;
+use crate::object::{HData,DecodableObject};
+use crate::errors::*;
+
+/// An event pushed by the relay for synced buffers.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Event {
+    BufferOpened(HData),
+    BufferTypeChanged(HData),
+    BufferMoved(HData),
+    BufferMerged(HData),
+    BufferUnmerged(HData),
+    BufferHidden(HData),
+    BufferUnhidden(HData),
+    BufferRenamed(HData),
+    BufferTitleChanged(HData),
+    BufferLocalVarAdded(HData),
+    BufferLocalVarChanged(HData),
+    BufferLocalVarRemoved(HData),
+    BufferClosing(HData),
+    BufferCleared(HData),
+    BufferLineAdded(HData),
+    Nicklist(HData),
+    NicklistDiff(HData),
+    Upgrade,
+    UpgradeEnded,
+}
+
+impl Event {
+    pub(crate) fn decode<R: Read + ?Sized>(id: Id, r: &mut R) -> Result<Self> {
+        let hdata = |r: &mut R| HData::decode(r).chain_err(|| format!("decoding {:?} event", id));
+
+        Ok(match id {
+            Id::BufferOpened          => Event::BufferOpened(hdata(r)?),
+            Id::BufferTypeChanged     => Event::BufferTypeChanged(hdata(r)?),
+            Id::BufferMoved           => Event::BufferMoved(hdata(r)?),
+            Id::BufferMerged          => Event::BufferMerged(hdata(r)?),
+            Id::BufferUnmerged        => Event::BufferUnmerged(hdata(r)?),
+            Id::BufferHidden          => Event::BufferHidden(hdata(r)?),
+            Id::BufferUnhidden        => Event::BufferUnhidden(hdata(r)?),
+            Id::BufferRenamed         => Event::BufferRenamed(hdata(r)?),
+            Id::BufferTitleChanged    => Event::BufferTitleChanged(hdata(r)?),
+            Id::BufferLocalVarAdded   => Event::BufferLocalVarAdded(hdata(r)?),
+            Id::BufferLocalVarChanged => Event::BufferLocalVarChanged(hdata(r)?),
+            Id::BufferLocalVarRemoved => Event::BufferLocalVarRemoved(hdata(r)?),
+            Id::BuffferClosing        => Event::BufferClosing(hdata(r)?),
+            Id::BufferCleared         => Event::BufferCleared(hdata(r)?),
+            Id::BufferLineAdded       => Event::BufferLineAdded(hdata(r)?),
+            Id::Nicklist              => Event::Nicklist(hdata(r)?),
+            Id::NicklistDiff          => Event::NicklistDiff(hdata(r)?),
+            Id::Upgrade               => Event::Upgrade,
+            Id::UpgradeEnded          => Event::UpgradeEnded,
+            Id::Pong | Id::Other(_)   => bail!("{:?} is not an event", id),
+        })
+    }
+}
@@ -0,0 +1,302 @@
+//! Unread counts of buffers, as shown in the hotlist of WeeChat.
+//!
+//! Fetch the hotlist once with `Client::fetch_hotlist` and pass every event to `Hotlist::apply`
+//! to count new lines. The relay doesn't send an event when a buffer is read within WeeChat,
+//! so fetch the hotlist again when it needs to be exact.
+
+use std::convert::TryFrom;
+use crate::event::Event;
+use crate::lines::Line;
+use crate::object::{HData,HDataItem,Pointer};
+use crate::errors::*;
+
+/// How important the unread lines of a buffer are, from least to most.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum Priority {
+    /// E.g. joins and parts.
+    Low = 0,
+    Message = 1,
+    /// Private messages.
+    Private = 2,
+    Highlight = 3,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [Priority::Low, Priority::Message, Priority::Private, Priority::Highlight];
+
+    /// The priority WeeChat adds `line` to the hotlist with,
+    /// or `None` if it isn't added, e.g. for lines tagged with `notify_none`.
+    pub fn of_line(line: &Line) -> Option<Priority> {
+        if line.highlight() {
+            return Some(Priority::Highlight);
+        }
+
+        if line.has_tag("notify_none") {
+            None
+        } else if line.has_tag("notify_highlight") {
+            Some(Priority::Highlight)
+        } else if line.has_tag("notify_private") {
+            Some(Priority::Private)
+        } else if line.has_tag("notify_message") {
+            Some(Priority::Message)
+        } else {
+            Some(Priority::Low)
+        }
+    }
+}
+
+impl TryFrom<i32> for Priority {
+    type Error = Error;
+
+    fn try_from(priority: i32) -> Result<Self> {
+        Ok(match priority {
+            0 => Priority::Low,
+            1 => Priority::Message,
+            2 => Priority::Private,
+            3 => Priority::Highlight,
+            other => bail!("unknown hotlist priority {}", other),
+        })
+    }
+}
+
+/// The buffers with unread lines.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Hotlist {
+    entries: Vec<Entry>,
+}
+
+impl Hotlist {
+    /// The hdata path to request the hotlist from.
+    pub const HDATA_PATH: &'static str = "hotlist:gui_hotlist(*)";
+    /// The keys needed to build the hotlist.
+    pub const HDATA_KEYS: &'static [&'static str] = &["priority", "buffer", "count"];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The buffers with unread lines, in the order WeeChat sent them or they got their first line.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get(&self, buffer: &Pointer) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.buffer == *buffer)
+    }
+
+    /// Returns the number of unread lines of `buffer` with `priority`.
+    pub fn count(&self, buffer: &Pointer, priority: Priority) -> u32 {
+        self.get(buffer).map_or(0, |entry| entry.count(priority))
+    }
+
+    /// Replaces all entries with the ones in `hdata`,
+    /// as received in response to requesting `HDATA_PATH`.
+    pub fn update(&mut self, hdata: &HData) -> Result<()> {
+        self.entries = hdata.items().iter()
+            .map(Entry::try_from)
+            .collect::<Result<_>>()?;
+
+        Ok(())
+    }
+
+    /// Counts `line` the way WeeChat does.
+    ///
+    /// Returns the priority it was counted with, if any.
+    /// Lines that aren't displayed, e.g. filtered ones, are not counted.
+    pub fn add_line(&mut self, line: &Line) -> Option<Priority> {
+        if !line.displayed() {
+            return None;
+        }
+
+        let priority = Priority::of_line(line)?;
+
+        let index = match self.entries.iter().position(|entry| entry.buffer == *line.buffer()) {
+            Some(index) => index,
+            None => {
+                self.entries.push(Entry {
+                    buffer: line.buffer().clone(),
+                    priority,
+                    counts: [0; 4],
+                });
+                self.entries.len() - 1
+            },
+        };
+
+        let entry = &mut self.entries[index];
+        entry.priority = entry.priority.max(priority);
+        entry.counts[priority as usize] += 1;
+
+        Some(priority)
+    }
+
+    /// Removes `buffer` from the hotlist, e.g. once it was read.
+    ///
+    /// Use `Client::mark_read` to also remove it within WeeChat.
+    pub fn remove(&mut self, buffer: &Pointer) -> Option<Entry> {
+        let index = self.entries.iter().position(|entry| entry.buffer == *buffer)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Applies the changes of an event and returns the buffers whose counts changed.
+    ///
+    /// New lines are counted and closed buffers are removed, other events are ignored.
+    pub fn apply(&mut self, event: &Event) -> Result<Vec<Pointer>> {
+        let mut changed = Vec::new();
+
+        match *event {
+            Event::BufferLineAdded(ref hdata) => {
+                for item in hdata.items() {
+                    let line = Line::try_from(item)?;
+
+                    if self.add_line(&line).is_some() {
+                        changed.push(line.buffer().clone());
+                    }
+                }
+            },
+            Event::BufferClosing(ref hdata) => {
+                for item in hdata.items() {
+                    let buffer = item.pointers().first().ok_or("missing buffer pointer")?;
+
+                    if self.remove(buffer).is_some() {
+                        changed.push(buffer.clone());
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        Ok(changed)
+    }
+}
+
+impl TryFrom<&HData> for Hotlist {
+    type Error = Error;
+
+    fn try_from(hdata: &HData) -> Result<Self> {
+        let mut hotlist = Hotlist::new();
+        hotlist.update(hdata)?;
+        Ok(hotlist)
+    }
+}
+
+/// The unread lines of a buffer.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Entry {
+    buffer: Pointer,
+    priority: Priority,
+    counts: [u32; 4],
+}
+
+impl Entry {
+    pub fn buffer(&self) -> &Pointer {
+        &self.buffer
+    }
+
+    /// The highest priority of the unread lines.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Returns the number of unread lines with `priority`.
+    pub fn count(&self, priority: Priority) -> u32 {
+        self.counts[priority as usize]
+    }
+
+    /// The number of unread lines of all priorities.
+    pub fn total(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+}
+
+impl TryFrom<&HDataItem> for Entry {
+    type Error = Error;
+
+    fn try_from(item: &HDataItem) -> Result<Self> {
+        let count = item.get_array("count")?;
+        ensure!(count.len() == 4, "expected 4 hotlist counts, got {}", count.len());
+
+        let mut counts = [0; 4];
+        for (count, object) in counts.iter_mut().zip(count) {
+            let value = object.as_int().ok_or(ErrorKind::UnexpectedType)?;
+            *count = value.max(0) as u32;
+        }
+
+        Ok(Entry {
+            buffer: item.get_pointer("buffer")?.clone(),
+            priority: Priority::try_from(item.get_int("priority")?)?,
+            counts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::object::Object;
+
+    fn entry(buffer: usize, priority: i32, counts: [i32; 4]) -> HDataItem {
+        HDataItem::new(vec![Pointer::from(0x100 + buffer)], hashmap!{
+            Object::str("priority") => Object::int(priority),
+            Object::str("buffer") => Object::pointer(buffer),
+            Object::str("count") => Object::array(counts.iter().map(|&count| Object::int(count)).collect::<Vec<_>>()),
+        })
+    }
+
+    fn line(pointer: usize, buffer: usize, highlight: bool, tags: &[&str]) -> HDataItem {
+        HDataItem::new(vec![Pointer::from(pointer)], hashmap!{
+            Object::str("buffer") => Object::pointer(buffer),
+            Object::str("date") => Object::time(1000u64),
+            Object::str("date_printed") => Object::time(1000u64),
+            Object::str("displayed") => Object::char(1),
+            Object::str("highlight") => Object::char(highlight as i8),
+            Object::str("tags_array") => Object::array(tags.iter().map(|&tag| Object::str(tag)).collect::<Vec<_>>()),
+            Object::str("prefix") => Object::str("nick"),
+            Object::str("message") => Object::str("hello"),
+        })
+    }
+
+    #[test]
+    fn decode() {
+        let hotlist = Hotlist::try_from(&HData::new("hotlist", Vec::new(), vec![
+            entry(0x1, 3, [2, 5, 0, 1]),
+            entry(0x2, 0, [4, 0, 0, 0]),
+        ])).unwrap();
+
+        let entry = hotlist.get(&Pointer::from(0x1)).unwrap();
+        assert_eq!(entry.priority(), Priority::Highlight);
+        assert_eq!(entry.count(Priority::Message), 5);
+        assert_eq!(entry.total(), 8);
+        assert_eq!(hotlist.count(&Pointer::from(0x2), Priority::Low), 4);
+        assert_eq!(hotlist.count(&Pointer::from(0x3), Priority::Low), 0);
+    }
+
+    #[test]
+    fn apply() {
+        let mut hotlist = Hotlist::try_from(&HData::new("hotlist", Vec::new(), vec![
+            entry(0x1, 0, [1, 0, 0, 0]),
+        ])).unwrap();
+
+        let changed = hotlist.apply(&Event::BufferLineAdded(HData::new("line_data", Vec::new(), vec![
+            line(0x10, 0x1, false, &["irc_privmsg", "notify_message"]),
+            line(0x11, 0x1, true, &["irc_privmsg", "notify_message"]),
+            line(0x12, 0x2, false, &["irc_privmsg", "notify_private"]),
+            line(0x13, 0x2, false, &["irc_privmsg", "self_msg", "notify_none"]),
+        ]))).unwrap();
+        assert_eq!(changed, vec![Pointer::from(0x1), Pointer::from(0x1), Pointer::from(0x2)]);
+
+        let entry = hotlist.get(&Pointer::from(0x1)).unwrap();
+        assert_eq!(entry.priority(), Priority::Highlight);
+        assert_eq!(Priority::ALL.iter().map(|&priority| entry.count(priority)).collect::<Vec<_>>(), vec![1, 1, 0, 1]);
+
+        let entry = hotlist.get(&Pointer::from(0x2)).unwrap();
+        assert_eq!(entry.priority(), Priority::Private);
+        assert_eq!(entry.total(), 1);
+
+        let changed = hotlist.apply(&Event::BufferClosing(HData::new("buffer", Vec::new(), vec![
+            HDataItem::new(vec![Pointer::from(0x2)], HashMap::<Object,Object>::new()),
+        ]))).unwrap();
+        assert_eq!(changed, vec![Pointer::from(0x2)]);
+        assert!(hotlist.get(&Pointer::from(0x2)).is_none());
+    }
+}
@@ -1,29 +1,60 @@
-#![allow(warnings)]
-#![feature(proc_macro, conservative_impl_trait, generators, try_from)]
+// error_chain errors carry their cause chain and backtrace, boxing every `Result` would not help much
+#![allow(clippy::result_large_err)]
 
 #[macro_use]
 extern crate error_chain;
-extern crate futures_await as futures;
-extern crate tokio_core;
-extern crate tokio_io;
-extern crate byteorder;
-extern crate libflate;
-extern crate typemap;
-extern crate hexdump;
+#[macro_use]
+extern crate tracing;
 #[cfg(test)]
 #[macro_use]
 extern crate maplit;
 
+#[allow(unexpected_cfgs)] // error_chain checks a cfg set by its own build script
 mod errors;
 mod raw;
 mod command;
 mod message;
 mod message_resolver;
+mod transport;
+mod totp;
 pub mod object;
+pub mod connection;
 pub mod client;
+pub mod blocking;
+pub mod auth;
+pub mod event;
+pub mod completion;
+pub mod nicklist;
+pub mod address;
+pub mod reconnect;
+pub mod state;
+pub mod lines;
+pub mod hotlist;
+pub mod color;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "websocket")]
+pub mod ws;
 
-pub use object::Object;
+pub use object::{Object,EncodableObject};
 
-pub use client::Client;
+pub use client::{Client,SyncOption,BufferId};
+pub use connection::Connection;
+pub use blocking::BlockingClient;
+pub use transport::{Transport,connect_tcp};
+#[cfg(unix)]
+pub use transport::connect_unix;
+pub use address::Address;
+pub use reconnect::ReconnectingClient;
+pub use auth::AuthOptions;
+pub use raw::{Compression,encode_message};
+pub use event::Event;
+pub use completion::Completion;
+pub use nicklist::Nicklist;
+pub use state::State;
+pub use lines::{Lines,Line};
+pub use hotlist::Hotlist;
 
 pub use errors::*;
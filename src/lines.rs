@@ -0,0 +1,247 @@
+//! The lines of formatted buffers.
+//!
+//! Lines arrive with `_buffer_line_added` while synced and older ones are fetched
+//! with `Client::fetch_backlog`. `State` keeps one `Lines` per buffer.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use crate::object::{HDataItem,Pointer};
+use crate::errors::*;
+
+/// The lines of a buffer, oldest first.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Lines {
+    lines: Vec<Line>,
+    /// The pointers of `lines`, to find known lines without a linear scan.
+    pointers: HashSet<Pointer>,
+}
+
+impl Lines {
+    /// The keys needed to build lines from a backlog request.
+    pub const HDATA_KEYS: &'static [&'static str] = &[
+        "date", "date_printed", "displayed", "highlight", "tags_array", "prefix", "message",
+    ];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn contains(&self, pointer: &Pointer) -> bool {
+        self.pointers.contains(pointer)
+    }
+
+    /// Appends a line received with `_buffer_line_added`.
+    ///
+    /// Returns `false` if the line is already known.
+    pub fn push(&mut self, line: Line) -> bool {
+        if !self.pointers.insert(line.pointer.clone()) {
+            return false;
+        }
+
+        self.lines.push(line);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.pointers.clear();
+    }
+
+    /// Merges `backlog`, oldest first, with the lines already known.
+    ///
+    /// Lines already received are skipped, the others are inserted by the time they were printed.
+    /// Returns the number of new lines.
+    pub fn merge(&mut self, backlog: Vec<Line>) -> usize {
+        let backlog = backlog.into_iter()
+            .filter(|line| self.pointers.insert(line.pointer.clone()))
+            .collect::<Vec<_>>();
+        let added = backlog.len();
+
+        let mut merged = Vec::with_capacity(self.lines.len() + added);
+        let mut known = self.lines.drain(..).peekable();
+
+        // The backlog comes first if both were printed in the same second
+        for line in backlog {
+            while let Some(next) = known.peek() {
+                if next.date_printed >= line.date_printed {
+                    break;
+                }
+                merged.extend(known.next());
+            }
+
+            merged.push(line);
+        }
+
+        merged.extend(known);
+        self.lines = merged;
+
+        added
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Line {
+    pointer: Pointer,
+    buffer: Pointer,
+    date: u64,
+    date_printed: u64,
+    displayed: bool,
+    highlight: bool,
+    tags: Vec<String>,
+    prefix: String,
+    message: String,
+}
+
+impl Line {
+    /// Identifies the line within WeeChat.
+    pub fn pointer(&self) -> &Pointer {
+        &self.pointer
+    }
+
+    pub fn buffer(&self) -> &Pointer {
+        &self.buffer
+    }
+
+    /// The date displayed in front of the line, in seconds since the UNIX epoch.
+    pub fn date(&self) -> u64 {
+        self.date
+    }
+
+    /// When the line was printed, in seconds since the UNIX epoch.
+    pub fn date_printed(&self) -> u64 {
+        self.date_printed
+    }
+
+    /// Whether the line is displayed, i.e. not filtered.
+    pub fn displayed(&self) -> bool {
+        self.displayed
+    }
+
+    pub fn highlight(&self) -> bool {
+        self.highlight
+    }
+
+    /// The tags of the line, e.g. `irc_privmsg` or `notify_message`.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// The prefix, usually the nick, including color codes.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The message, including color codes.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Decodes a line from an item with the path `line_data`, as sent with `_buffer_line_added`,
+/// or a path starting with `buffer` and ending with `line_data`, as fetched for the backlog.
+impl TryFrom<&HDataItem> for Line {
+    type Error = Error;
+
+    fn try_from(item: &HDataItem) -> Result<Self> {
+        let pointer = item.pointers().last().ok_or("missing line pointer")?;
+        let buffer = match item.get("buffer") {
+            Some(_) => item.get_pointer("buffer")?,
+            None => item.pointers().first().filter(|_| item.pointers().len() > 1).ok_or("missing buffer pointer")?,
+        };
+
+        let tags = item.get_array("tags_array")?.iter()
+            .map(|tag| tag.as_str().map(String::from).ok_or_else(|| ErrorKind::UnexpectedType.into()))
+            .collect::<Result<_>>()?;
+
+        Ok(Line {
+            pointer: pointer.clone(),
+            buffer: buffer.clone(),
+            date: item.get_time("date")?.timestamp()?,
+            date_printed: item.get_time("date_printed")?.timestamp()?,
+            displayed: item.get_char("displayed")? != 0,
+            highlight: item.get_char("highlight")? != 0,
+            tags,
+            prefix: item.get_str("prefix")?.into(),
+            message: item.get_str("message")?.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::object::Object;
+
+    fn line(pointer: usize, date_printed: u64, message: &str) -> Line {
+        let values: HashMap<Object,Object> = hashmap!{
+            Object::str("buffer") => Object::pointer(0x1),
+            Object::str("date") => Object::time(date_printed),
+            Object::str("date_printed") => Object::time(date_printed),
+            Object::str("displayed") => Object::char(1),
+            Object::str("highlight") => Object::char(0),
+            Object::str("tags_array") => Object::array(vec![Object::str("notify_message")]),
+            Object::str("prefix") => Object::str("nick"),
+            Object::str("message") => Object::str(message),
+        };
+
+        Line::try_from(&HDataItem::new(vec![Pointer::from(pointer)], values)).unwrap()
+    }
+
+    fn messages(lines: &Lines) -> Vec<&str> {
+        lines.lines().iter().map(Line::message).collect()
+    }
+
+    #[test]
+    fn decode() {
+        let line = line(0x10, 1000, "hello");
+
+        assert_eq!(line.pointer(), &Pointer::from(0x10));
+        assert_eq!(line.buffer(), &Pointer::from(0x1));
+        assert_eq!(line.date_printed(), 1000);
+        assert!(line.displayed());
+        assert!(!line.highlight());
+        assert!(line.has_tag("notify_message"));
+        assert_eq!(line.prefix(), "nick");
+    }
+
+    #[test]
+    fn merge() {
+        let mut lines = Lines::new();
+        lines.push(line(0x13, 1002, "c"));
+        lines.push(line(0x14, 1003, "d"));
+        assert!(!lines.push(line(0x14, 1003, "d")));
+
+        let added = lines.merge(vec![
+            line(0x10, 1000, "x"),
+            line(0x11, 1002, "a"),
+            line(0x12, 1002, "b"),
+            line(0x13, 1002, "c"),
+            line(0x12, 1002, "b"),
+        ]);
+
+        assert_eq!(added, 3);
+        assert_eq!(messages(&lines), vec!["x", "a", "b", "c", "d"]);
+        assert!(lines.contains(&Pointer::from(0x10)));
+
+        lines.clear();
+        assert!(!lines.contains(&Pointer::from(0x10)));
+        assert!(lines.push(line(0x10, 1000, "x")));
+    }
+}
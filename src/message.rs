@@ -1,7 +1,6 @@
-use std::convert::TryFrom;
 use std::io::Read;
-use errors::*;
-use object::{Str,DecodableObject};
+use crate::errors::*;
+use crate::object::{Str,DecodableObject};
 
 #[derive(Debug,PartialEq,Eq,Hash)]
 pub(crate) enum Id {
@@ -28,13 +27,9 @@ pub(crate) enum Id {
     Other(String),
 }
 
-impl<'a, R: Read + ?Sized> TryFrom<&'a mut R> for Id {
-    type Error = Error;
-
-    fn try_from(reader: &mut R) -> Result<Self> {
-        println!(">>> Reading id");
+impl Id {
+    pub fn decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
         let id = Str::decode_bare(reader)?;
-        println!("Id: {:?}", id);
 
         Ok(match id.as_str() {
             "_buffer_opened" => Id::BufferOpened,
@@ -62,27 +57,13 @@ impl<'a, R: Read + ?Sized> TryFrom<&'a mut R> for Id {
     }
 }
 
-#[derive(Debug)]
-pub enum Message {
-    Pong(Pong)
-}
-
 #[derive(Debug)]
 pub struct Pong(pub Str);
 
-impl<'a, R: Read + ?Sized> TryFrom<&'a mut R> for Pong {
-    type Error = Error;
-
-    fn try_from(reader: &mut R) -> Result<Self> {
-        println!(">>> Decoding [Pong]");
+impl Pong {
+    pub fn decode<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
         let msg = Str::decode(reader)?;
 
         Ok(Pong(msg))
     }
 }
-
-impl From<Pong> for Message {
-    fn from(m: Pong) -> Self {
-        Message::Pong(m)
-    }
-}
@@ -1,75 +1,295 @@
-use typemap::{TypeMap,Key};
 use std::collections::{HashMap,VecDeque};
-use std::io::Read;
-use std::convert::TryFrom;
-use std::marker::PhantomData;
-use futures::sync::oneshot;
-use message::{self,Id};
-use object::{self,DecodableObject};
-use errors::*;
+use futures::channel::oneshot;
+use futures::channel::mpsc::{unbounded,UnboundedSender,UnboundedReceiver};
+use crate::connection::{Message,Response};
+use crate::object;
+use crate::client::Promise;
+use crate::event::Event;
+use crate::completion::Completion;
+use crate::nicklist::Nicklist;
+use tracing::Span;
+use crate::errors::*;
 
 pub(crate) struct MessageResolver {
-    resolvers: HashMap<String, Box<Resolver>>,
-    pong_promises: VecDeque<oneshot::Sender<String>>,
+    resolvers: HashMap<String, (Span, Box<dyn Resolver + Send>)>,
+    auth_promise: Option<oneshot::Sender<Result<()>>>,
+    pong_promises: VecDeque<oneshot::Sender<Result<String>>>,
+    event_subscribers: Vec<UnboundedSender<Event>>,
+    close_subscribers: Vec<oneshot::Sender<Result<()>>>,
+    closed: Option<Closed>,
+}
+
+/// Why the connection was closed.
+enum Closed {
+    /// The client was dropped.
+    Locally,
+    /// The connection failed, e.g. because of an I/O or protocol error.
+    Failed(Error),
 }
 
 impl MessageResolver {
     pub fn new() -> Self {
         MessageResolver {
             resolvers: HashMap::new(),
+            auth_promise: None,
             pong_promises: VecDeque::new(),
+            event_subscribers: Vec::new(),
+            close_subscribers: Vec::new(),
+            closed: None,
         }
     }
 
-    pub fn resolve<R: Read>(&mut self, r: &mut R) -> Result<()> {
-        let id = Id::try_from(r)
-            .chain_err(|| "decoding id")?;
-
-        match id {
-            Id::Pong => {
-                self.pong_promises
+    pub fn resolve(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Authenticated => {
+                if let Some(tx) = self.auth_promise.take() {
+                    // Sending only fails if nobody waits for the promise anymore, which is fine here and below
+                    let _ = tx.send(Ok(()));
+                }
+            },
+            Message::Pong(pong) => {
+                let _ = self.pong_promises
                     .pop_front().ok_or(ErrorKind::MissingResponsePromise)?
-                    .send(message::Pong::try_from(r).chain_err(|| "decoding pong")?.0.into());
+                    .send(Ok(pong));
+            },
+            Message::Response(id, response) => {
+                let (span, resolver) = self.resolvers
+                    .remove(&id).ok_or(ErrorKind::MissingResponsePromise)?;
+                span.in_scope(|| {
+                    debug!(target: "weechat_relay::client", "resolving response");
+                    resolver.resolve(response)
+                });
             },
-            Id::Other(id) => {
-                self.resolvers
-                    .remove(&id).ok_or(ErrorKind::MissingResponsePromise)?
-                    .resolve(r).chain_err(|| "using resolver")?;
+            Message::Event(event) => {
+                self.event_subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
             },
-            _ => unimplemented!(),
         }
 
         Ok(())
     }
 
-    pub fn register<T: Resolver + 'static>(&mut self, id: String, resolvable: T) {
-        self.resolvers.insert(id, Box::new(resolvable));
+    pub fn register<T: Resolver + Send + 'static>(&mut self, id: String, resolvable: T) {
+        let span = debug_span!(target: "weechat_relay::client", "request", id = id.as_str());
+
+        if let Some(ref closed) = self.closed {
+            span.in_scope(|| debug!(target: "weechat_relay::client", "connection already closed, rejecting request"));
+            Box::new(resolvable).reject(closed.rejection());
+            return;
+        }
+
+        span.in_scope(|| debug!(target: "weechat_relay::client", "waiting for response"));
+        self.resolvers.insert(id, (span, Box::new(resolvable)));
     }
 
-    pub fn register_promise<T>(&mut self, id: String) -> oneshot::Receiver<T> where
-        oneshot::Sender<T>: Resolver,
-        T: 'static,
+    pub fn register_promise<T>(&mut self, id: String) -> Promise<T> where
+        oneshot::Sender<Result<T>>: Resolver,
+        T: Send + 'static,
     {
         let (tx, rx) = oneshot::channel();
         self.register(id, tx);
-        rx
+        Promise::new(rx)
+    }
+
+    /// Returns a promise that resolves once the relay accepted the credentials.
+    pub fn register_auth(&mut self) -> Promise<()> {
+        let (tx, rx) = oneshot::channel();
+        match self.closed {
+            Some(ref closed) => { let _ = tx.send(Err(closed.rejection())); },
+            None => self.auth_promise = Some(tx),
+        }
+        Promise::new(rx)
     }
 
-    pub fn register_pong(&mut self) -> oneshot::Receiver<String> {
+    pub fn register_pong(&mut self) -> Promise<String> {
         let (tx, rx) = oneshot::channel();
-        self.pong_promises.push_back(tx);
+        match self.closed {
+            Some(ref closed) => { let _ = tx.send(Err(closed.rejection())); },
+            None => self.pong_promises.push_back(tx),
+        }
+        Promise::new(rx)
+    }
+
+    pub fn subscribe_events(&mut self) -> UnboundedReceiver<Event> {
+        let (tx, rx) = unbounded();
+        if self.closed.is_none() {
+            self.event_subscribers.push(tx);
+        }
         rx
     }
+
+    /// Returns a promise that resolves once the connection is closed,
+    /// or fails with the reason the connection failed.
+    pub fn subscribe_closed(&mut self) -> Promise<()> {
+        let (tx, rx) = oneshot::channel();
+        match self.closed {
+            Some(ref closed) => { let _ = tx.send(closed.reason()); },
+            None => self.close_subscribers.push(tx),
+        }
+        Promise::new(rx)
+    }
+
+    /// Fails all pending requests, ends all event streams
+    /// and notifies the close subscribers.
+    ///
+    /// `reason` is the error that killed the connection, if any.
+    pub fn close(&mut self, reason: Result<()>) {
+        let closed = match reason {
+            Ok(()) => Closed::Locally,
+            Err(err) => Closed::Failed(err),
+        };
+
+        for (_, (span, resolver)) in self.resolvers.drain() {
+            span.in_scope(|| debug!(target: "weechat_relay::client", "connection closed, rejecting request"));
+            resolver.reject(closed.rejection());
+        }
+
+        if let Some(tx) = self.auth_promise.take() {
+            let _ = tx.send(Err(closed.rejection()));
+        }
+
+        for tx in self.pong_promises.drain(..) {
+            let _ = tx.send(Err(closed.rejection()));
+        }
+
+        for tx in self.close_subscribers.drain(..) {
+            let _ = tx.send(closed.reason());
+        }
+
+        self.event_subscribers.clear();
+        self.closed = Some(closed);
+    }
+}
+
+impl Closed {
+    /// The error for requests that can't be answered anymore.
+    fn rejection(&self) -> Error {
+        match *self {
+            Closed::Locally => ErrorKind::Disconnected.into(),
+            Closed::Failed(ref err) => Error::with_chain(replicate(err), ErrorKind::Disconnected),
+        }
+    }
+
+    fn reason(&self) -> Result<()> {
+        match *self {
+            Closed::Locally => Ok(()),
+            Closed::Failed(ref err) => Err(replicate(err)),
+        }
+    }
+}
+
+/// Copies the messages of the error chain of `err`, since errors can't be cloned.
+fn replicate(err: &Error) -> Error {
+    if let ErrorKind::Disconnected = *err.kind() {
+        return ErrorKind::Disconnected.into();
+    }
+
+    let mut messages = err.iter().map(|cause| cause.to_string()).collect::<Vec<_>>();
+    let root = Error::from(messages.pop().unwrap_or_default());
+
+    messages.into_iter().rev().fold(root, Error::with_chain)
 }
 
 pub(crate) trait Resolver {
-    fn resolve(self: Box<Self>, r: &mut Read) -> Result<()>;
+    fn resolve(self: Box<Self>, response: Result<Response>);
+    fn reject(self: Box<Self>, err: Error);
 }
 
-impl Resolver for oneshot::Sender<object::InfoList> {
-    fn resolve(self: Box<Self>, r: &mut Read) -> Result<()> {
-        let infolist = object::InfoList::decode(r).chain_err(|| "decoding InfoList")?;
-        self.send(infolist);
-        Ok(())
+impl<T: FromResponse> Resolver for oneshot::Sender<Result<T>> {
+    // The promise may have been dropped, then nobody is interested in the response
+    fn resolve(self: Box<Self>, response: Result<Response>) {
+        let _ = self.send(response.and_then(T::from_response));
+    }
+
+    fn reject(self: Box<Self>, err: Error) {
+        let _ = self.send(Err(err));
+    }
+}
+
+/// The value of a response to a request with an id.
+pub(crate) trait FromResponse: Sized {
+    fn from_response(response: Response) -> Result<Self>;
+}
+
+impl FromResponse for object::Info {
+    fn from_response(response: Response) -> Result<Self> {
+        match response {
+            Response::Info(info) => Ok(info),
+            _ => bail!(ErrorKind::UnexpectedType),
+        }
+    }
+}
+
+impl FromResponse for object::InfoList {
+    fn from_response(response: Response) -> Result<Self> {
+        match response {
+            Response::InfoList(infolist) => Ok(infolist),
+            _ => bail!(ErrorKind::UnexpectedType),
+        }
+    }
+}
+
+impl FromResponse for object::HData {
+    fn from_response(response: Response) -> Result<Self> {
+        match response {
+            Response::HData(hdata) => Ok(hdata),
+            _ => bail!(ErrorKind::UnexpectedType),
+        }
+    }
+}
+
+impl FromResponse for Option<Completion> {
+    fn from_response(response: Response) -> Result<Self> {
+        match response {
+            Response::Completion(completion) => Ok(completion),
+            _ => bail!(ErrorKind::UnexpectedType),
+        }
+    }
+}
+
+impl FromResponse for Nicklist {
+    fn from_response(response: Response) -> Result<Self> {
+        match response {
+            Response::Nicklist(nicklist) => Ok(nicklist),
+            _ => bail!(ErrorKind::UnexpectedType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::io;
+
+    #[test]
+    fn close() {
+        let mut resolver = MessageResolver::new();
+        let info = resolver.register_promise::<object::Info>("1".into());
+        let pong = resolver.register_pong();
+        let closed = resolver.subscribe_closed();
+        let reason = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer"))
+            .chain_err(|| "reading message");
+
+        resolver.close(Err(reason));
+
+        let err = block_on(info).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::Disconnected));
+        assert_eq!(err.iter().map(|cause| cause.to_string()).collect::<Vec<_>>()[1..], ["reading message", "reset by peer"]);
+        assert!(block_on(pong).is_err());
+        assert_eq!(block_on(closed).unwrap_err().to_string(), "reading message");
+
+        // Requests after closing fail immediately
+        assert!(block_on(resolver.register_promise::<object::InfoList>("2".into())).is_err());
+    }
+
+    #[test]
+    fn close_locally() {
+        let mut resolver = MessageResolver::new();
+        let info = resolver.register_promise::<object::Info>("1".into());
+
+        resolver.close(Ok(()));
+
+        assert!(matches!(*block_on(info).unwrap_err().kind(), ErrorKind::Disconnected));
+        assert!(block_on(resolver.subscribe_closed()).is_ok());
     }
 }
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::object::{HData,HDataItem,Pointer};
+use crate::errors::*;
+
+/// The nicklists of one or more buffers, indexed by buffer pointer.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Nicklist {
+    buffers: HashMap<Pointer, Group>,
+}
+
+impl Nicklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the root group of the nicklist of `buffer`.
+    pub fn buffer(&self, buffer: &Pointer) -> Option<&Group> {
+        self.buffers.get(buffer)
+    }
+
+    pub fn buffers(&self) -> &HashMap<Pointer, Group> {
+        &self.buffers
+    }
+
+    /// Replaces the nicklists of all buffers contained in `hdata`,
+    /// as received with `_nicklist` or in response to a `nicklist` command.
+    pub fn update(&mut self, hdata: &HData) -> Result<()> {
+        let mut stacks: HashMap<Pointer, Vec<usize>> = HashMap::new();
+
+        for item in hdata.items() {
+            let buffer = buffer_pointer(item)?;
+            let entry = Entry::try_from(item)?;
+
+            if !stacks.contains_key(buffer) {
+                self.buffers.remove(buffer);
+            }
+
+            let stack = stacks.entry(buffer.clone()).or_default();
+
+            match entry {
+                Entry::Group(group) => {
+                    if group.level <= 0 || !self.buffers.contains_key(buffer) {
+                        self.buffers.insert(buffer.clone(), group);
+                        stack.clear();
+                        continue;
+                    }
+
+                    stack.truncate(group.level as usize - 1);
+
+                    let root = self.buffers.get_mut(buffer).unwrap();
+                    let parent = group_at_mut(root, stack)?;
+                    parent.groups.push(group);
+                    stack.push(parent.groups.len() - 1);
+                },
+                Entry::Nick(nick) => {
+                    let root = self.buffers.get_mut(buffer)
+                        .ok_or("nick without group")?;
+                    group_at_mut(root, stack)?.nicks.push(nick);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the changes of a `_nicklist_diff` event.
+    ///
+    /// The diff is applied to copies of the affected nicklists,
+    /// so nothing is changed if it fails.
+    /// A diff starting at the root group of an unknown buffer creates its nicklist.
+    pub fn apply_diff(&mut self, hdata: &HData) -> Result<()> {
+        let mut changed: HashMap<Pointer, Group> = HashMap::new();
+        let mut current: HashMap<Pointer, Vec<usize>> = HashMap::new();
+
+        for item in hdata.items() {
+            let buffer = buffer_pointer(item)?;
+            let diff = item.get_char("_diff")? as u8;
+            let entry = Entry::try_from(item)?;
+
+            if !changed.contains_key(buffer) {
+                match (self.buffers.get(buffer), diff, &entry) {
+                    (Some(root), _, _) => { changed.insert(buffer.clone(), root.clone()); },
+                    (None, b'^', Entry::Group(group)) if group.level <= 0 => {
+                        changed.insert(buffer.clone(), group.clone());
+                    },
+                    (None, _, _) => bail!("nicklist diff for unknown buffer {}", buffer),
+                }
+            }
+
+            let root = changed.get_mut(buffer).unwrap();
+
+            if diff == b'^' {
+                let name = entry.name();
+                let path = root.find_group_path(name)
+                    .ok_or_else(|| format!("unknown nicklist group '{}'", name))?;
+                current.insert(buffer.clone(), path);
+                continue;
+            }
+
+            let path = current.get(buffer).map(Vec::as_slice).unwrap_or(&[]);
+            let group = group_at_mut(root, path)?;
+
+            match (diff, entry) {
+                (b'+', Entry::Group(added)) => group.groups.push(added),
+                (b'+', Entry::Nick(added)) => group.nicks.push(added),
+                (b'-', Entry::Group(removed)) => group.groups.retain(|group| group.name != removed.name),
+                (b'-', Entry::Nick(removed)) => group.nicks.retain(|nick| nick.name != removed.name),
+                (b'*', Entry::Group(updated)) => {
+                    if let Some(group) = group.groups.iter_mut().find(|group| group.name == updated.name) {
+                        group.update(updated);
+                    }
+                },
+                (b'*', Entry::Nick(updated)) => {
+                    if let Some(nick) = group.nicks.iter_mut().find(|nick| nick.name == updated.name) {
+                        *nick = updated;
+                    }
+                },
+                (diff, _) => bail!("unknown nicklist diff '{}'", diff as char),
+            }
+        }
+
+        self.buffers.extend(changed);
+        Ok(())
+    }
+}
+
+impl TryFrom<&HData> for Nicklist {
+    type Error = Error;
+
+    fn try_from(hdata: &HData) -> Result<Self> {
+        let mut nicklist = Nicklist::new();
+        nicklist.update(hdata)?;
+        Ok(nicklist)
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Group {
+    name: String,
+    color: String,
+    visible: bool,
+    level: i32,
+    groups: Vec<Group>,
+    nicks: Vec<Nick>,
+}
+
+impl Group {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self) -> &str {
+        &self.color
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    pub fn nicks(&self) -> &[Nick] {
+        &self.nicks
+    }
+
+    /// Returns the group named `name`, searching this group and all of its subgroups.
+    pub fn find_group(&self, name: &str) -> Option<&Group> {
+        self.find_group_path(name).map(|path| {
+            path.iter().fold(self, |group, &i| &group.groups[i])
+        })
+    }
+
+    fn find_group_path(&self, name: &str) -> Option<Vec<usize>> {
+        if self.name == name {
+            return Some(Vec::new());
+        }
+
+        for (i, group) in self.groups.iter().enumerate() {
+            if let Some(mut path) = group.find_group_path(name) {
+                path.insert(0, i);
+                return Some(path);
+            }
+        }
+
+        None
+    }
+
+    fn update(&mut self, other: Group) {
+        self.color = other.color;
+        self.visible = other.visible;
+        self.level = other.level;
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Nick {
+    name: String,
+    color: String,
+    prefix: String,
+    prefix_color: String,
+    visible: bool,
+    level: i32,
+}
+
+impl Nick {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self) -> &str {
+        &self.color
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn prefix_color(&self) -> &str {
+        &self.prefix_color
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+}
+
+enum Entry {
+    Group(Group),
+    Nick(Nick),
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        match *self {
+            Entry::Group(ref group) => &group.name,
+            Entry::Nick(ref nick) => &nick.name,
+        }
+    }
+}
+
+impl TryFrom<&HDataItem> for Entry {
+    type Error = Error;
+
+    fn try_from(item: &HDataItem) -> Result<Self> {
+        let name = item.get_str("name")?.into();
+        let color = item.get_str("color")?.into();
+        let visible = item.get_char("visible")? != 0;
+        let level = item.get_int("level")?;
+
+        Ok(if item.get_char("group")? != 0 {
+            Entry::Group(Group {
+                name,
+                color,
+                visible,
+                level,
+                groups: Vec::new(),
+                nicks: Vec::new(),
+            })
+        } else {
+            Entry::Nick(Nick {
+                name,
+                color,
+                prefix: item.get_str("prefix")?.into(),
+                prefix_color: item.get_str("prefix_color")?.into(),
+                visible,
+                level,
+            })
+        })
+    }
+}
+
+fn buffer_pointer(item: &HDataItem) -> Result<&Pointer> {
+    item.pointers().first().ok_or_else(|| "missing buffer pointer".into())
+}
+
+fn group_at_mut<'a>(root: &'a mut Group, path: &[usize]) -> Result<&'a mut Group> {
+    path.iter().try_fold(root, |group, &i| {
+        group.groups.get_mut(i).ok_or_else(|| "invalid nicklist group path".into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    fn group(buffer: usize, pointer: usize, name: &str, level: i32) -> HDataItem {
+        HDataItem::new(vec![Pointer::from(buffer), Pointer::from(pointer)], hashmap!{
+            Object::str("group") => Object::char(1),
+            Object::str("visible") => Object::char(1),
+            Object::str("level") => Object::int(level),
+            Object::str("name") => Object::str(name),
+            Object::str("color") => Object::str("weechat.color.nicklist_group"),
+            Object::str("prefix") => Object::str(None::<String>),
+            Object::str("prefix_color") => Object::str(None::<String>),
+        })
+    }
+
+    fn nick(buffer: usize, pointer: usize, name: &str, prefix: &str) -> HDataItem {
+        HDataItem::new(vec![Pointer::from(buffer), Pointer::from(pointer)], hashmap!{
+            Object::str("group") => Object::char(0),
+            Object::str("visible") => Object::char(1),
+            Object::str("level") => Object::int(0),
+            Object::str("name") => Object::str(name),
+            Object::str("color") => Object::str("bar_fg"),
+            Object::str("prefix") => Object::str(prefix),
+            Object::str("prefix_color") => Object::str("lightgreen"),
+        })
+    }
+
+    fn diff(diff: u8, item: HDataItem) -> HDataItem {
+        let mut values = item.values().clone();
+        values.insert(Object::str("_diff"), Object::char(diff as i8));
+
+        HDataItem::new(item.pointers().to_vec(), values)
+    }
+
+    fn nicklist() -> Nicklist {
+        Nicklist::try_from(&HData::new("buffer/nicklist_group", Vec::new(), vec![
+            group(0x1, 0x10, "root", 0),
+            group(0x1, 0x11, "000|o", 1),
+            nick(0x1, 0x20, "alice", "@"),
+            group(0x1, 0x12, "999|...", 1),
+            nick(0x1, 0x21, "bob", " "),
+            group(0x2, 0x30, "root", 0),
+        ])).unwrap()
+    }
+
+    fn nicks(group: &Group) -> Vec<&str> {
+        group.nicks().iter().map(Nick::name).collect()
+    }
+
+    fn apply(nicklist: &mut Nicklist, items: Vec<HDataItem>) -> Result<()> {
+        nicklist.apply_diff(&HData::new("buffer/nicklist_group", Vec::new(), items))
+    }
+
+    #[test]
+    fn update() {
+        let nicklist = nicklist();
+        let root = nicklist.buffer(&Pointer::from(0x1)).unwrap();
+
+        assert_eq!(root.groups().iter().map(Group::name).collect::<Vec<_>>(), vec!["000|o", "999|..."]);
+        assert_eq!(nicks(root.find_group("000|o").unwrap()), vec!["alice"]);
+        assert_eq!(root.find_group("999|...").unwrap().nicks()[0].prefix(), " ");
+        assert!(nicklist.buffer(&Pointer::from(0x2)).unwrap().groups().is_empty());
+
+        // Buffers not contained in the update are kept
+        let mut nicklist = nicklist.clone();
+        nicklist.update(&HData::new("buffer/nicklist_group", Vec::new(), vec![group(0x1, 0x10, "root", 0)])).unwrap();
+        assert!(nicklist.buffer(&Pointer::from(0x1)).unwrap().groups().is_empty());
+        assert!(nicklist.buffer(&Pointer::from(0x2)).is_some());
+    }
+
+    #[test]
+    fn diff_add_and_remove() {
+        let mut nicklist = nicklist();
+
+        apply(&mut nicklist, vec![
+            diff(b'^', group(0x1, 0x11, "000|o", 1)),
+            diff(b'+', nick(0x1, 0x22, "carol", "@")),
+            diff(b'-', nick(0x1, 0x20, "alice", "@")),
+            diff(b'^', group(0x1, 0x10, "root", 0)),
+            diff(b'+', group(0x1, 0x13, "001|v", 1)),
+            diff(b'-', group(0x1, 0x12, "999|...", 1)),
+        ]).unwrap();
+
+        let root = nicklist.buffer(&Pointer::from(0x1)).unwrap();
+        assert_eq!(root.groups().iter().map(Group::name).collect::<Vec<_>>(), vec!["000|o", "001|v"]);
+        assert_eq!(nicks(&root.groups()[0]), vec!["carol"]);
+    }
+
+    #[test]
+    fn diff_update() {
+        let mut nicklist = nicklist();
+
+        apply(&mut nicklist, vec![
+            diff(b'^', group(0x1, 0x12, "999|...", 1)),
+            diff(b'*', nick(0x1, 0x21, "bob", "+")),
+            diff(b'^', group(0x1, 0x10, "root", 0)),
+            diff(b'*', group(0x1, 0x11, "000|o", 2)),
+        ]).unwrap();
+
+        let root = nicklist.buffer(&Pointer::from(0x1)).unwrap();
+        assert_eq!(root.find_group("999|...").unwrap().nicks()[0].prefix(), "+");
+        assert_eq!(root.find_group("000|o").unwrap().level(), 2);
+    }
+
+    #[test]
+    fn diff_errors() {
+        let mut nicklist = nicklist();
+        let before = nicklist.clone();
+
+        assert!(apply(&mut nicklist, vec![diff(b'^', group(0x9, 0x90, "000|o", 1))]).is_err());
+        assert!(apply(&mut nicklist, vec![diff(b'+', nick(0x9, 0x91, "dave", " "))]).is_err());
+        assert!(apply(&mut nicklist, vec![diff(b'^', group(0x1, 0x14, "unknown", 1))]).is_err());
+        assert!(apply(&mut nicklist, vec![diff(b'?', nick(0x1, 0x20, "alice", "@"))]).is_err());
+
+        // Nothing is applied if a later part of the diff fails
+        assert!(apply(&mut nicklist, vec![
+            diff(b'^', group(0x1, 0x10, "root", 0)),
+            diff(b'-', group(0x1, 0x11, "000|o", 1)),
+            diff(b'^', group(0x1, 0x14, "unknown", 1)),
+        ]).is_err());
+        assert_eq!(nicklist, before);
+    }
+
+    #[test]
+    fn diff_unknown_buffer() {
+        let mut nicklist = nicklist();
+
+        apply(&mut nicklist, vec![
+            diff(b'^', group(0x9, 0x90, "root", 0)),
+            diff(b'+', group(0x9, 0x91, "000|o", 1)),
+            diff(b'^', group(0x9, 0x91, "000|o", 1)),
+            diff(b'+', nick(0x9, 0x92, "dave", "@")),
+        ]).unwrap();
+
+        let root = nicklist.buffer(&Pointer::from(0x9)).unwrap();
+        assert_eq!(root.name(), "root");
+        assert_eq!(nicks(&root.groups()[0]), vec!["dave"]);
+        assert!(nicklist.buffer(&Pointer::from(0x1)).is_some());
+    }
+}
@@ -1,7 +1,7 @@
-use std::io::Read;
-use byteorder::{ReadBytesExt,BigEndian as BE};
-use super::{Object,Tag,DecodableObject,read_tag};
-use errors::*;
+use std::io::{Read,Write};
+use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian as BE};
+use super::{Object,Str,Tag,DecodableObject,EncodableObject,read_tag,capacity};
+use crate::errors::*;
 
 pub type Array = Vec<Object>;
 
@@ -12,7 +12,7 @@ impl DecodableObject for Array {
         let elem_tag = read_tag(r)?;
         let decode_elem = Object::bare_decoder_from_tag(elem_tag)?;
         let len = r.read_u32::<BE>()?;
-        let mut array = Vec::with_capacity(len as usize);
+        let mut array = Vec::with_capacity(capacity(len as usize));
 
         for _ in 0..len {
             let object = decode_elem(r).chain_err(|| ErrorKind::Decoding)?;
@@ -22,3 +22,23 @@ impl DecodableObject for Array {
         Ok(array)
     }
 }
+
+impl EncodableObject for Array {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        let elem_tag = self.first().map(Object::tag).unwrap_or(Str::TAG);
+
+        w.write_all(elem_tag)?;
+        w.write_u32::<BE>(self.len() as u32)?;
+
+        for elem in self {
+            ensure!(elem.tag() == elem_tag, ErrorKind::UnexpectedType);
+            elem.encode_bare(w)?;
+        }
+
+        Ok(())
+    }
+}
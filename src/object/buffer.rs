@@ -1,8 +1,8 @@
-use std::io::Read;
-use super::{Tag,DecodableObject,Int};
-use errors::*;
+use std::io::{Read,Write};
+use super::{Tag,DecodableObject,EncodableObject,Int,capacity};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Buffer(Option<Vec<u8>>);
 
 impl DecodableObject for Buffer {
@@ -15,7 +15,7 @@ impl DecodableObject for Buffer {
             return Ok(Buffer(None));
         }
 
-        let mut buffer = Vec::with_capacity(len as usize);
+        let mut buffer = Vec::with_capacity(capacity(len.max(0) as usize));
 
         r.take(len as u64).read_to_end(&mut buffer)?;
 
@@ -23,9 +23,26 @@ impl DecodableObject for Buffer {
     }
 }
 
+impl EncodableObject for Buffer {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        match self.0 {
+            None => (-1 as Int).encode_bare(w),
+            Some(ref buffer) => {
+                (buffer.len() as Int).encode_bare(w)?;
+                w.write_all(buffer)?;
+                Ok(())
+            },
+        }
+    }
+}
+
 impl From<Buffer> for Vec<u8> {
     fn from(s: Buffer) -> Self {
-        s.0.unwrap_or_else(Vec::new)
+        s.0.unwrap_or_default()
     }
 }
 
@@ -47,7 +64,7 @@ impl From<Option<Vec<u8>>> for Buffer {
     }
 }
 
-impl<'a> From<&'a [u8]> for Buffer {
+impl From<&[u8]> for Buffer {
     fn from(s: &[u8]) -> Self {
         Buffer(Some(Vec::from(s)))
     }
@@ -1,7 +1,7 @@
-use std::io::Read;
-use byteorder::ReadBytesExt;
-use super::{Tag,DecodableObject};
-use errors::*;
+use std::io::{Read,Write};
+use byteorder::{ReadBytesExt,WriteBytesExt};
+use super::{Tag,DecodableObject,EncodableObject};
+use crate::errors::*;
 
 pub type Char = i8;
 
@@ -13,3 +13,14 @@ impl DecodableObject for Char {
         Ok(n)
     }
 }
+
+impl EncodableObject for Char {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        w.write_i8(*self)?;
+        Ok(())
+    }
+}
@@ -1,12 +1,12 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use std::collections::HashMap;
 use std::ops::{Deref,DerefMut};
-use byteorder::{ReadBytesExt,BigEndian as BE};
+use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian as BE};
 use std::hash::{Hash,Hasher};
-use super::{Object,Tag,DecodableObject,read_tag};
-use errors::*;
+use super::{Object,Str,Tag,DecodableObject,EncodableObject,read_tag,capacity};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq)]
 pub struct HashTable(HashMap<Object,Object>);
 
 impl Hash for HashTable {
@@ -53,7 +53,7 @@ impl DecodableObject for HashTable {
         let decode_key = Object::bare_decoder_from_tag(key_tag)?;
         let decode_value = Object::bare_decoder_from_tag(value_tag)?;
         let len = r.read_u32::<BE>()?;
-        let mut hm = HashMap::with_capacity(len as usize);
+        let mut hm = HashMap::with_capacity(capacity(len as usize));
 
         for _ in 0..len {
             let key = decode_key(r)?;
@@ -64,3 +64,28 @@ impl DecodableObject for HashTable {
         Ok(HashTable(hm))
     }
 }
+
+impl EncodableObject for HashTable {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        let (key_tag, value_tag) = match self.0.iter().next() {
+            Some((key, value)) => (key.tag(), value.tag()),
+            None => (Str::TAG, Str::TAG),
+        };
+
+        w.write_all(key_tag)?;
+        w.write_all(value_tag)?;
+        w.write_u32::<BE>(self.0.len() as u32)?;
+
+        for (key, value) in &self.0 {
+            ensure!(key.tag() == key_tag && value.tag() == value_tag, ErrorKind::UnexpectedType);
+            key.encode_bare(w)?;
+            value.encode_bare(w)?;
+        }
+
+        Ok(())
+    }
+}
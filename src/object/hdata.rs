@@ -0,0 +1,215 @@
+use std::io::{Read,Write};
+use std::collections::HashMap;
+use super::{Object,HashTable,Pointer,Str,Char,Int,Long,Time,Array,Tag,DecodableObject,EncodableObject,capacity};
+use crate::errors::*;
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct HData {
+    path: Str,
+    keys: Vec<HDataKey>,
+    items: Vec<HDataItem>,
+}
+
+impl HData {
+    pub fn new<S: Into<String>>(path: S, keys: Vec<HDataKey>, items: Vec<HDataItem>) -> Self {
+        HData {
+            path: path.into().into(),
+            keys,
+            items,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn keys(&self) -> &[HDataKey] {
+        &self.keys
+    }
+
+    pub fn items(&self) -> &[HDataItem] {
+        &self.items
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct HDataKey {
+    name: String,
+    tag: [u8; 3],
+}
+
+impl HDataKey {
+    pub fn new<S: Into<String>>(name: S, tag: Tag) -> Self {
+        HDataKey {
+            name: name.into(),
+            tag: *tag,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tag(&self) -> &[u8; 3] {
+        &self.tag
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct HDataItem {
+    pointers: Vec<Pointer>,
+    values: HashTable,
+}
+
+impl HDataItem {
+    pub fn new<T: Into<HashTable>>(pointers: Vec<Pointer>, values: T) -> Self {
+        HDataItem {
+            pointers,
+            values: values.into(),
+        }
+    }
+
+    /// The pointers of the p-path, one for each element of the h-path.
+    pub fn pointers(&self) -> &[Pointer] {
+        &self.pointers
+    }
+
+    pub fn values(&self) -> &HashTable {
+        &self.values
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Object> {
+        self.values.get(&Object::str(key))
+    }
+
+    pub fn get_char(&self, key: &str) -> Result<Char> {
+        self.get_as(key, Object::as_char)
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Int> {
+        self.get_as(key, Object::as_int)
+    }
+
+    pub fn get_long(&self, key: &str) -> Result<Long> {
+        self.get_as(key, Object::as_long)
+    }
+
+    pub fn get_str(&self, key: &str) -> Result<&str> {
+        self.get_as(key, Object::as_str)
+    }
+
+    pub fn get_pointer(&self, key: &str) -> Result<&Pointer> {
+        self.get_as(key, Object::as_pointer)
+    }
+
+    pub fn get_time(&self, key: &str) -> Result<&Time> {
+        self.get_as(key, Object::as_time)
+    }
+
+    pub fn get_hash_table(&self, key: &str) -> Result<&HashTable> {
+        self.get_as(key, Object::as_hash_table)
+    }
+
+    pub fn get_array(&self, key: &str) -> Result<&Array> {
+        self.get_as(key, Object::as_array)
+    }
+
+    fn get_as<'a, T, F>(&'a self, key: &str, f: F) -> Result<T> where
+        F: FnOnce(&'a Object) -> Option<T>,
+    {
+        let value = self.get(key).ok_or_else(|| ErrorKind::MissingKey(key.into()))?;
+        f(value).ok_or_else(|| ErrorKind::UnexpectedType.into())
+    }
+}
+
+impl DecodableObject for HData {
+    const TAG: Tag = b"hda";
+
+    fn decode_bare<R: Read + ?Sized>(r: &mut R) -> Result<Self> {
+        let path = Str::decode_bare(r).chain_err(|| "decoding h-path")?;
+        let keys = Str::decode_bare(r).chain_err(|| "decoding keys")?;
+        let keys = parse_keys(&keys)?;
+        let len = Int::decode_bare(r).chain_err(|| "decoding count")?;
+        trace!(target: "weechat_relay::object", path = path.as_str(), len, "decoding hdata");
+        let path_len = path.split('/').count();
+        let mut items = Vec::with_capacity(capacity(len.max(0) as usize));
+
+        for _ in 0..len {
+            let mut pointers = Vec::with_capacity(path_len);
+
+            for _ in 0..path_len {
+                let pointer = Pointer::decode_bare(r).chain_err(|| "decoding p-path")?;
+                pointers.push(pointer);
+            }
+
+            let mut values = HashMap::with_capacity(keys.len());
+
+            for key in &keys {
+                let decode = Object::bare_decoder_from_tag(key.tag)?;
+                let value = decode(r).chain_err(|| format!("decoding value of '{}'", key.name))?;
+
+                values.insert(Object::str(key.name.as_str()), value);
+            }
+
+            items.push(HDataItem {
+                pointers,
+                values: HashTable::from(values),
+            });
+        }
+
+        Ok(HData { path, keys, items })
+    }
+}
+
+impl EncodableObject for HData {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        let keys = self.keys.iter()
+            .map(|key| format!("{}:{}", key.name, String::from_utf8_lossy(&key.tag)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.path.encode_bare(w)?;
+        Str::from(keys).encode_bare(w)?;
+        (self.items.len() as Int).encode_bare(w)?;
+
+        for item in &self.items {
+            for pointer in &item.pointers {
+                pointer.encode_bare(w)?;
+            }
+
+            for key in &self.keys {
+                let value = item.get(&key.name).ok_or_else(|| ErrorKind::MissingKey(key.name.clone()))?;
+                ensure!(value.tag() == &key.tag, ErrorKind::UnexpectedType);
+                value.encode_bare(w)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a key list of the form `name1:type1,name2:type2`.
+fn parse_keys(keys: &str) -> Result<Vec<HDataKey>> {
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    keys.split(',')
+        .map(|key| {
+            let mut parts = key.splitn(2, ':');
+            let name = parts.next().unwrap_or("");
+            let ty = parts.next().unwrap_or("").as_bytes();
+
+            ensure!(ty.len() == 3, ErrorKind::Decoding);
+
+            Ok(HDataKey {
+                name: name.into(),
+                tag: [ty[0], ty[1], ty[2]],
+            })
+        })
+        .collect()
+}
@@ -1,8 +1,8 @@
-use std::io::Read;
-use super::{Tag,DecodableObject,Str};
-use errors::*;
+use std::io::{Read,Write};
+use super::{Tag,DecodableObject,EncodableObject,Str};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Info {
     name: Str,
     value: Str,
@@ -29,6 +29,17 @@ impl DecodableObject for Info {
     }
 }
 
+impl EncodableObject for Info {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        self.name.encode_bare(w)?;
+        self.value.encode_bare(w)
+    }
+}
+
 impl<S1,S2> From<(S1,S2)> for Info where
     S1: Into<String>,
     S2: Into<String>,
@@ -1,10 +1,10 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use std::collections::HashMap;
-use byteorder::{ReadBytesExt,BigEndian as BE};
-use super::{Object,HashTable,Str,Tag,DecodableObject};
-use errors::*;
+use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian as BE};
+use super::{Object,HashTable,Str,Tag,DecodableObject,EncodableObject,capacity};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct InfoList {
     name: Str,
     items: Vec<HashTable>,
@@ -25,15 +25,13 @@ impl DecodableObject for InfoList {
 
     fn decode_bare<R: Read + ?Sized>(r: &mut R) -> Result<Self> {
         let name = Str::decode_bare(r).chain_err(|| "decoding name")?;
-        println!(">>> name: {}", name.as_str());
         let len = r.read_u32::<BE>().chain_err(|| "decoding len")?;
-        println!(">>> num_entries: {}", len);
-        let mut items = Vec::with_capacity(len as usize);
-
+        trace!(target: "weechat_relay::object", name = name.as_str(), len, "decoding infolist");
+        let mut items = Vec::with_capacity(capacity(len as usize));
 
         for _ in 0..len {
             let len = r.read_u32::<BE>().chain_err(|| "decoding num items")?;
-            let mut item = HashMap::with_capacity(len as usize);
+            let mut item = HashMap::with_capacity(capacity(len as usize));
 
             for _ in 0..len {
                 let name = Str::decode_bare(r).map(Object::Str).chain_err(|| "decoding item name")?;
@@ -48,3 +46,35 @@ impl DecodableObject for InfoList {
         Ok(InfoList { name, items })
     }
 }
+
+impl EncodableObject for InfoList {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        self.name.encode_bare(w)?;
+        w.write_u32::<BE>(self.items.len() as u32)?;
+
+        for item in &self.items {
+            w.write_u32::<BE>(item.len() as u32)?;
+
+            for (name, value) in item.iter() {
+                let name = name.as_str().ok_or(ErrorKind::UnexpectedType)?;
+                Str::from(name).encode_bare(w)?;
+                value.encode(w)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Into<String>> From<(S, Vec<HashTable>)> for InfoList {
+    fn from((name, items): (S, Vec<HashTable>)) -> Self {
+        InfoList {
+            name: name.into().into(),
+            items,
+        }
+    }
+}
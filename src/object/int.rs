@@ -1,7 +1,7 @@
-use std::io::Read;
-use byteorder::{ReadBytesExt,BigEndian as BE};
-use super::{Tag,DecodableObject};
-use errors::*;
+use std::io::{Read,Write};
+use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian as BE};
+use super::{Tag,DecodableObject,EncodableObject};
+use crate::errors::*;
 
 pub type Int = i32;
 
@@ -13,3 +13,14 @@ impl DecodableObject for Int {
         Ok(n)
     }
 }
+
+impl EncodableObject for Int {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        w.write_i32::<BE>(*self)?;
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use byteorder::ReadBytesExt;
-use super::{Tag,DecodableObject};
-use errors::*;
+use super::{Tag,DecodableObject,EncodableObject,write_short_str};
+use crate::errors::*;
 
 pub type Long = i64;
 
@@ -19,3 +19,13 @@ impl DecodableObject for Long {
         Ok(n)
     }
 }
+
+impl EncodableObject for Long {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        write_short_str(w, &self.to_string())
+    }
+}
@@ -1,9 +1,7 @@
-use std::io::Read;
-use byteorder::{ReadBytesExt, BigEndian as BE};
-use errors::*;
-use std::collections::HashMap;
-use std::hash::{Hash,Hasher};
-use std::ops::{Deref,DerefMut};
+use std::io::{Read,Write};
+use byteorder::WriteBytesExt;
+use crate::errors::*;
+use std::hash::Hash;
 use std::fmt;
 
 mod str;
@@ -11,6 +9,7 @@ mod buffer;
 mod pointer;
 mod time;
 mod hash_table;
+mod hdata;
 mod info;
 mod info_list;
 mod array;
@@ -24,6 +23,7 @@ pub use self::buffer::Buffer;
 pub use self::pointer::Pointer;
 pub use self::time::Time;
 pub use self::hash_table::HashTable;
+pub use self::hdata::{HData,HDataKey,HDataItem};
 pub use self::info::Info;
 pub use self::info_list::InfoList;
 pub use self::array::Array;
@@ -31,7 +31,7 @@ pub use self::char::Char;
 pub use self::int::Int;
 pub use self::long::Long;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub enum Object {
     Char(Char),
     Int(Int),
@@ -41,6 +41,7 @@ pub enum Object {
     Pointer(Pointer),
     Time(Time),
     HashTable(HashTable),
+    HData(HData),
     Info(Info),
     InfoList(InfoList),
     Array(Array),
@@ -55,11 +56,54 @@ impl Object {
     pub fn pointer   <T: Into<Pointer  >>(t: T) -> Self { Object::Pointer  (t.into()) }
     pub fn time      <T: Into<Time     >>(t: T) -> Self { Object::Time     (t.into()) }
     pub fn hash_table<T: Into<HashTable>>(t: T) -> Self { Object::HashTable(t.into()) }
+    pub fn hdata     <T: Into<HData    >>(t: T) -> Self { Object::HData    (t.into()) }
     pub fn info      <T: Into<Info     >>(t: T) -> Self { Object::Info     (t.into()) }
     pub fn info_list <T: Into<InfoList >>(t: T) -> Self { Object::InfoList (t.into()) }
     pub fn array     <T: Into<Array    >>(t: T) -> Self { Object::Array    (t.into()) }
 }
 
+impl Object {
+    pub fn as_char(&self) -> Option<Char> {
+        match *self { Object::Char(ch) => Some(ch), _ => None }
+    }
+
+    pub fn as_int(&self) -> Option<Int> {
+        match *self { Object::Int(n) => Some(n), _ => None }
+    }
+
+    pub fn as_long(&self) -> Option<Long> {
+        match *self { Object::Long(n) => Some(n), _ => None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Object::Str(ref s) => Some(s.as_str()), _ => None }
+    }
+
+    pub fn as_buffer(&self) -> Option<&Buffer> {
+        match *self { Object::Buffer(ref buf) => Some(buf), _ => None }
+    }
+
+    pub fn as_pointer(&self) -> Option<&Pointer> {
+        match *self { Object::Pointer(ref ptr) => Some(ptr), _ => None }
+    }
+
+    pub fn as_time(&self) -> Option<&Time> {
+        match *self { Object::Time(ref time) => Some(time), _ => None }
+    }
+
+    pub fn as_hash_table(&self) -> Option<&HashTable> {
+        match *self { Object::HashTable(ref htb) => Some(htb), _ => None }
+    }
+
+    pub fn as_hdata(&self) -> Option<&HData> {
+        match *self { Object::HData(ref hdata) => Some(hdata), _ => None }
+    }
+
+    pub fn as_array(&self) -> Option<&Array> {
+        match *self { Object::Array(ref array) => Some(array), _ => None }
+    }
+}
+
 impl Object {
     fn decode<R: Read + ?Sized>(r: &mut R) -> Result<Self> {
         let tag = read_tag(r).chain_err(|| "reading tag")?;
@@ -77,6 +121,7 @@ impl Object {
             Pointer  ::TAG => |r| Pointer  ::decode_bare(r).map(Object::Pointer  ).chain_err(|| "Decoding Pointer"  ),
             Time     ::TAG => |r| Time     ::decode_bare(r).map(Object::Time     ).chain_err(|| "Decoding Time"     ),
             HashTable::TAG => |r| HashTable::decode_bare(r).map(Object::HashTable).chain_err(|| "Decoding HashTable"),
+            HData    ::TAG => |r| HData    ::decode_bare(r).map(Object::HData    ).chain_err(|| "Decoding HData"    ),
             Info     ::TAG => |r| Info     ::decode_bare(r).map(Object::Info     ).chain_err(|| "Decoding Info"     ),
             InfoList ::TAG => |r| InfoList ::decode_bare(r).map(Object::InfoList ).chain_err(|| "Decoding InfoList" ),
             Array    ::TAG => |r| Array    ::decode_bare(r).map(Object::Array    ).chain_err(|| "Decoding Array"    ),
@@ -85,6 +130,42 @@ impl Object {
     }
 }
 
+impl EncodableObject for Object {
+    fn tag(&self) -> Tag {
+        match *self {
+            Object::Char     (ref o) => o.tag(),
+            Object::Int      (ref o) => o.tag(),
+            Object::Long     (ref o) => o.tag(),
+            Object::Str      (ref o) => o.tag(),
+            Object::Buffer   (ref o) => o.tag(),
+            Object::Pointer  (ref o) => o.tag(),
+            Object::Time     (ref o) => o.tag(),
+            Object::HashTable(ref o) => o.tag(),
+            Object::HData    (ref o) => o.tag(),
+            Object::Info     (ref o) => o.tag(),
+            Object::InfoList (ref o) => o.tag(),
+            Object::Array    (ref o) => o.tag(),
+        }
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        match *self {
+            Object::Char     (ref o) => o.encode_bare(w).chain_err(|| "Encoding Char"     ),
+            Object::Int      (ref o) => o.encode_bare(w).chain_err(|| "Encoding Int"      ),
+            Object::Long     (ref o) => o.encode_bare(w).chain_err(|| "Encoding Long"     ),
+            Object::Str      (ref o) => o.encode_bare(w).chain_err(|| "Encoding Str"      ),
+            Object::Buffer   (ref o) => o.encode_bare(w).chain_err(|| "Encoding Buffer"   ),
+            Object::Pointer  (ref o) => o.encode_bare(w).chain_err(|| "Encoding Pointer"  ),
+            Object::Time     (ref o) => o.encode_bare(w).chain_err(|| "Encoding Time"     ),
+            Object::HashTable(ref o) => o.encode_bare(w).chain_err(|| "Encoding HashTable"),
+            Object::HData    (ref o) => o.encode_bare(w).chain_err(|| "Encoding HData"    ),
+            Object::Info     (ref o) => o.encode_bare(w).chain_err(|| "Encoding Info"     ),
+            Object::InfoList (ref o) => o.encode_bare(w).chain_err(|| "Encoding InfoList" ),
+            Object::Array    (ref o) => o.encode_bare(w).chain_err(|| "Encoding Array"    ),
+        }
+    }
+}
+
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -96,6 +177,7 @@ impl fmt::Display for Object {
             Object::Pointer(ref ptr) => ptr.fmt(f),
             Object::Time(ref time) => time.fmt(f),
             Object::HashTable(..) => write!(f, "<hash_table>"),
+            Object::HData(..) => write!(f, "<hdata>"),
             Object::Info(ref info) => write!(f, "({} => {})", info.name(), info.value()),
             Object::InfoList(..) => write!(f, "<info_list>"),
             Object::Array(..) => write!(f, "<array>"),
@@ -111,6 +193,21 @@ fn read_tag<R: Read + ?Sized>(r: &mut R) -> Result<[u8; 3]> {
     Ok(tag)
 }
 
+/// Limits pre-allocations by a count read from the wire,
+/// so that a malformed count can't force a huge allocation up front.
+fn capacity(len: usize) -> usize {
+    len.min(1024)
+}
+
+/// Writes a string prefixed with its length as a single byte,
+/// as used by `Long`, `Pointer` and `Time`.
+fn write_short_str<W: Write + ?Sized>(w: &mut W, s: &str) -> Result<()> {
+    ensure!(s.len() <= u8::MAX as usize, ErrorKind::InvalidMessageLength);
+    w.write_u8(s.len() as u8)?;
+    w.write_all(s.as_bytes())?;
+    Ok(())
+}
+
 pub(crate) trait DecodableObject {
     const TAG: Tag;
 
@@ -124,3 +221,14 @@ pub(crate) trait DecodableObject {
 
     fn decode_bare<R: Read + ?Sized>(r: &mut R) -> Result<Self> where Self: Sized;
 }
+
+pub trait EncodableObject {
+    fn tag(&self) -> Tag;
+
+    fn encode<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        w.write_all(self.tag())?;
+        self.encode_bare(w)
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()>;
+}
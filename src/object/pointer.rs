@@ -1,10 +1,10 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use std::fmt;
 use byteorder::ReadBytesExt;
-use super::{Tag,DecodableObject};
-use errors::*;
+use super::{Tag,DecodableObject,EncodableObject,write_short_str};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Pointer(String);
 
 impl DecodableObject for Pointer {
@@ -22,6 +22,16 @@ impl DecodableObject for Pointer {
     }
 }
 
+impl EncodableObject for Pointer {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        write_short_str(w, &self.0)
+    }
+}
+
 impl fmt::Display for Pointer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "0x{}", self.0)
@@ -1,14 +1,14 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use std::ops::Deref;
-use super::{Object,Tag,DecodableObject,Buffer};
-use errors::*;
+use super::{Object,Tag,DecodableObject,EncodableObject,Buffer,Int};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Str(Option<String>);
 
 impl Str {
     pub fn as_ref(&self) -> Option<&str> {
-        self.0.as_ref().map(String::as_str)
+        self.0.as_deref()
     }
 
     pub fn as_str(&self) -> &str {
@@ -31,6 +31,23 @@ impl DecodableObject for Str {
     }
 }
 
+impl EncodableObject for Str {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        match self.0 {
+            None => (-1 as Int).encode_bare(w),
+            Some(ref string) => {
+                (string.len() as Int).encode_bare(w)?;
+                w.write_all(string.as_bytes())?;
+                Ok(())
+            },
+        }
+    }
+}
+
 impl Deref for Str {
     type Target = str;
     fn deref(&self) -> &str {
@@ -46,7 +63,7 @@ impl From<Str> for Object {
 
 impl From<Str> for String {
     fn from(s: Str) -> Self {
-        s.0.unwrap_or_else(String::new)
+        s.0.unwrap_or_default()
     }
 }
 
@@ -68,7 +85,7 @@ impl From<Option<String>> for Str {
     }
 }
 
-impl<'a> From<&'a str> for Str {
+impl From<&str> for Str {
     fn from(s: &str) -> Self {
         Str(Some(String::from(s)))
     }
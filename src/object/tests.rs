@@ -56,6 +56,7 @@ fn pointer() {
 fn time() {
     assert_eq!(Time::TAG, b"tim");
     test::<Time,_>(b"\x0A1321993456", 1321993456);
+    assert_eq!(Time::from(1321993456).timestamp().unwrap(), 1321993456);
 }
 
 #[test]
@@ -72,7 +73,60 @@ fn hash_table() {
 
 #[test]
 fn hdata() {
-    unimplemented!("hdata");
+    assert_eq!(HData::TAG, b"hda");
+
+    let mut data = &b"\0\0\0\x06buffer\0\0\0\x18number:int,full_name:str\0\0\0\x02\
+        \x0512345\0\0\0\x01\0\0\0\x0Ccore.weechat\
+        \x0567890\0\0\0\x02\0\0\0\x0Airc.server"[..];
+    let hdata = HData::decode_bare(&mut data).unwrap();
+
+    assert!(data.is_empty());
+    assert_eq!(hdata.path(), "buffer");
+    assert_eq!(hdata.keys().iter().map(HDataKey::name).collect::<Vec<_>>(), vec!["number", "full_name"]);
+    assert_eq!(hdata.keys()[0].tag(), b"int");
+    assert_eq!(hdata.keys()[1].tag(), b"str");
+    assert_eq!(hdata.items().len(), 2);
+
+    let item = &hdata.items()[0];
+    assert_eq!(item.pointers(), &[Pointer::from(0x12345)]);
+    assert_eq!(item.get("number"), Some(&Object::int(1)));
+    assert_eq!(item.get("full_name"), Some(&Object::str("core.weechat")));
+
+    let item = &hdata.items()[1];
+    assert_eq!(item.pointers(), &[Pointer::from(0x67890)]);
+    assert_eq!(item.get("number"), Some(&Object::int(2)));
+    assert_eq!(item.get("full_name"), Some(&Object::str("irc.server")));
+}
+
+#[test]
+fn hdata_path() {
+    let mut data = &b"\0\0\0\x0Cbuffer/lines\0\0\0\x0Anumber:int\0\0\0\x01\
+        \x03abc\x03def\0\0\0\x07"[..];
+    let hdata = HData::decode_bare(&mut data).unwrap();
+
+    assert!(data.is_empty());
+    assert_eq!(hdata.items().len(), 1);
+    assert_eq!(hdata.items()[0].pointers(), &[Pointer::from(0xabc), Pointer::from(0xdef)]);
+    assert_eq!(hdata.items()[0].get("number"), Some(&Object::int(7)));
+}
+
+#[test]
+fn hdata_empty() {
+    let mut data = &b"\0\0\0\0\0\0\0\0\0\0\0\0"[..];
+    let hdata = HData::decode_bare(&mut data).unwrap();
+
+    assert!(data.is_empty());
+    assert!(hdata.keys().is_empty());
+    assert!(hdata.items().is_empty());
+}
+
+#[test]
+fn huge_count() {
+    // Counts are only trusted as far as items are actually read
+    assert!(HData::decode_bare(&mut &b"\0\0\0\x06buffer\0\0\0\x0Anumber:int\x7F\xFF\xFF\xFF"[..]).is_err());
+    assert!(Array::decode_bare(&mut &b"int\xFF\xFF\xFF\xFF"[..]).is_err());
+    assert!(HashTable::decode_bare(&mut &b"strint\xFF\xFF\xFF\xFF"[..]).is_err());
+    assert!(InfoList::decode_bare(&mut &b"\0\0\0\x06buffer\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF"[..]).is_err());
 }
 
 #[test]
@@ -84,7 +138,9 @@ fn info() {
 #[test]
 fn info_list() {
     assert_eq!(InfoList::TAG, b"inl");
-    unimplemented!();
+    test::<InfoList,_>(b"\0\0\0\x06buffer\0\0\0\x01\0\0\0\x01\0\0\0\x06numberint\0\0\0\x01", ("buffer", vec![
+        HashTable::from(hashmap!{ Object::str("number") => Object::int(1) }),
+    ]));
 }
 
 #[test]
@@ -93,3 +149,71 @@ fn array() {
     test::<Array,_>(b"str\0\0\0\x02\0\0\0\x03abc\0\0\0\x02de", vec![Object::str("abc"), Object::str("de")]);
     test::<Array,_>(b"int\0\0\0\x03\0\0\0\x7B\0\0\x01\xC8\0\0\x03\x15", vec![Object::int(123), Object::int(456), Object::int(789)]);
 }
+
+fn test_encode<T,E>(data: &[u8], object: E) where
+    T: EncodableObject,
+    E: Into<T>,
+{
+    let mut actual = Vec::new();
+    object.into().encode_bare(&mut actual).unwrap();
+    assert_eq!(actual, data);
+}
+
+fn roundtrip<T>(object: T) where
+    T: DecodableObject + EncodableObject + Debug + PartialEq,
+{
+    let mut data = Vec::new();
+    object.encode(&mut data).unwrap();
+    assert_eq!(T::decode(&mut data.as_slice()).unwrap(), object);
+}
+
+#[test]
+fn encode() {
+    test_encode::<Char,_>(b"\x41", b'A' as Char);
+    test_encode::<Int,_>(b"\xFF\xFE\x1D\xC0", -123456);
+    test_encode::<Long,_>(b"\x0B-1234567890", -1234567890);
+    test_encode::<Str,_>(b"\0\0\0\x05hello", "hello");
+    test_encode::<Str,_>(b"\xff\xff\xff\xff", None);
+    test_encode::<Buffer,_>(b"\0\0\0\0", &[][..]);
+    test_encode::<Buffer,_>(b"\xff\xff\xff\xff", None);
+    test_encode::<Pointer,_>(b"\x091a2b3c4d5", 0x1a2b3c4d5);
+    test_encode::<Time,_>(b"\x0A1321993456", 1321993456);
+    test_encode::<Info,_>(b"\0\0\0\x04name\0\0\0\x05value", ("name", "value"));
+    test_encode::<Array,_>(b"str\0\0\0\x02\0\0\0\x03abc\0\0\0\x02de", vec![Object::str("abc"), Object::str("de")]);
+    test_encode::<HashTable,_>(
+        b"strint\0\0\0\x01\0\0\0\x04key1\0\0\0\x7B",
+        hashmap! { Object::str("key1") => Object::int(123) }
+    );
+}
+
+#[test]
+fn roundtrip_compound() {
+    roundtrip(HashTable::from(hashmap! {
+        Object::str("key1") => Object::str("abc"),
+        Object::str("key2") => Object::str("def"),
+    }));
+
+    roundtrip(InfoList::from(("buffer", vec![
+        HashTable::from(hashmap! {
+            Object::str("number") => Object::int(1),
+            Object::str("full_name") => Object::str("core.weechat"),
+        }),
+        HashTable::from(hashmap! {
+            Object::str("number") => Object::int(2),
+            Object::str("pointer") => Object::pointer(0x1234),
+        }),
+    ])));
+
+    roundtrip(HData::new(
+        "buffer/lines",
+        vec![HDataKey::new("number", Int::TAG), HDataKey::new("title", Str::TAG)],
+        vec![
+            HDataItem::new(vec![Pointer::from(0xabc), Pointer::from(0xdef)], hashmap! {
+                Object::str("number") => Object::int(1),
+                Object::str("title") => Object::str("Hello"),
+            }),
+        ],
+    ));
+
+    roundtrip::<Array>(vec![Object::array(vec![Object::long(1)]), Object::array(vec![])]);
+}
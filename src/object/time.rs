@@ -1,12 +1,19 @@
-use std::io::Read;
+use std::io::{Read,Write};
 use std::fmt;
 use byteorder::ReadBytesExt;
-use super::{Tag,DecodableObject,Buffer};
-use errors::*;
+use super::{Tag,DecodableObject,EncodableObject,write_short_str};
+use crate::errors::*;
 
-#[derive(Debug,PartialEq,Eq,Hash)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct Time(String);
 
+impl Time {
+    /// Seconds since the UNIX epoch.
+    pub fn timestamp(&self) -> Result<u64> {
+        self.0.parse().chain_err(|| format!("invalid time '{}'", self.0))
+    }
+}
+
 impl DecodableObject for Time {
     const TAG: Tag = b"tim";
 
@@ -20,6 +27,16 @@ impl DecodableObject for Time {
     }
 }
 
+impl EncodableObject for Time {
+    fn tag(&self) -> Tag {
+        <Self as DecodableObject>::TAG
+    }
+
+    fn encode_bare<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
+        write_short_str(w, &self.0)
+    }
+}
+
 impl fmt::Display for Time {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(f)
@@ -1,17 +1,30 @@
-use futures::prelude::*;
-use tokio_io::io;
-use tokio_io::{AsyncRead,AsyncWrite};
-use std::io::{Read,BufRead};
+use std::io::{Read,Write};
 use std::convert::TryFrom;
+use std::str::FromStr;
 use std::mem::size_of;
-use byteorder::{ReadBytesExt,BigEndian};
-use errors::*;
+use crate::errors::*;
 use libflate::zlib;
+use crate::auth::PasswordHashAlgo;
+use crate::object::{Object,Str,EncodableObject};
+use tracing::Level;
 
+/// Compression of the messages sent by the relay.
 #[derive(Copy,Clone,Debug,PartialEq,Eq)]
 pub enum Compression {
-    Off,
-    Zlib,
+    Off = 0,
+    Zlib = 1,
+    /// Requires WeeChat 3.5 or newer.
+    Zstd = 2,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Compression::Off  => "off",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+        }
+    }
 }
 
 impl TryFrom<u8> for Compression {
@@ -21,74 +34,176 @@ impl TryFrom<u8> for Compression {
         Ok(match byte {
             0 => Compression::Off,
             1 => Compression::Zlib,
+            2 => Compression::Zstd,
             code => Err(ErrorKind::UnknownCompression(code))?,
         })
     }
 }
 
-#[async]
-pub fn send_init<W: AsyncWrite + 'static>(writer: W, pass: String, compression: Compression) -> Result<W> {
-    let pass = pass.replace(",", r"\,");
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(compression: &str) -> Result<Self> {
+        Ok(match compression {
+            "off"  => Compression::Off,
+            "zlib" => Compression::Zlib,
+            "zstd" => Compression::Zstd,
+            _ => bail!("unknown compression '{}'", compression),
+        })
+    }
+}
+
+/// Encodes a complete message as sent by the relay,
+/// including the length and compression header.
+pub fn encode_message(id: &str, objects: &[Object], compression: Compression) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
 
-    let init = format!("init compression=off,password={}\n", pass);
+    Str::from(id).encode_bare(&mut data)?;
 
-    let (writer, _) = await!(io::write_all(writer, init))?;
+    for object in objects {
+        object.encode(&mut data)?;
+    }
 
-    Ok(writer)
-}
+    let data = match compression {
+        Compression::Off => data,
+        Compression::Zlib => {
+            let mut encoder = zlib::Encoder::new(Vec::new())?;
+            encoder.write_all(&data)?;
+            encoder.finish().into_result()?
+        },
+        Compression::Zstd => zstd::encode_all(data.as_slice(), 0)?,
+    };
 
-#[async]
-pub fn read_message<R: AsyncRead + 'static>(reader: R) -> Result<(R, Vec<u8>)> {
-    // Get message length
-    let (reader, len        ) = await!(read_u32(reader))?;
-    ensure!(len >= size_of::<u32>() as u32, ErrorKind::InvalidMessageLength);
-    let len = len - 4;
+    let len = size_of::<u32>() + 1 + data.len();
+    ensure!(len <= u32::MAX as usize, ErrorKind::InvalidMessageLength);
 
-    // Get message compression
-    ensure!(len >= 1 as u32, ErrorKind::InvalidMessageLength);
-    let (reader, compression) = await!(read_compression(reader))?;
-    let len = len - 1;
+    let mut message = Vec::with_capacity(len);
+    message.extend_from_slice(&(len as u32).to_be_bytes());
+    message.push(compression as u8);
+    message.extend(data);
 
-    let (reader, data) = await!(decompress(reader, len as usize, compression))?;
+    Ok(message)
+}
 
-    println!("Received (head):");
-    for line in ::hexdump::hexdump_iter(&data).take(10) {
-        println!("{}", line);
-    }
+/// Encodes the `handshake` command.
+pub fn encode_handshake(algos: &[PasswordHashAlgo], compression: Compression) -> String {
+    let algos = algos.iter().map(PasswordHashAlgo::as_str).collect::<Vec<_>>();
 
-    Ok((reader, data))
+    debug!(algos = %algos.join(":"), compression = compression.as_str(), "sending handshake");
+
+    format!(
+        "(handshake) handshake password_hash_algo={},compression={}\n",
+        algos.join(":"),
+        compression.as_str(),
+    )
 }
 
-#[async]
-fn read_u32<R: AsyncRead + 'static>(reader: R) -> Result<(R, u32)> {
-    let (reader, buf) = await!(io::read_exact(reader, [0; 4]))?;
-    let n = buf.as_ref().read_u32::<BigEndian>()?;
+/// Encodes the `init` command.
+///
+/// `auth` are the already formatted authentication arguments.
+pub fn encode_init(auth: &str, compression: Compression) -> String {
+    // The arguments contain the password, so they are never logged
+    debug!(compression = compression.as_str(), "sending init");
 
-    Ok((reader, n))
+    format!("init compression={},{}\n", compression.as_str(), auth)
 }
 
-#[async]
-fn read_compression<R: AsyncRead + 'static>(reader: R) -> Result<(R, Compression)> {
-    let (reader, buf) = await!(io::read_exact(reader, [0; 1]))?;
+/// Decodes the first message in `buf`.
+///
+/// Returns the decompressed message and the number of bytes it took up in `buf`,
+/// or `None` if `buf` doesn't contain a complete message yet.
+pub fn decode_message(buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    // Get message length and compression
+    if buf.len() < size_of::<u32>() + 1 {
+        return Ok(None);
+    }
 
-    let compression = Compression::try_from(buf[0])?;
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    ensure!(len > size_of::<u32>(), ErrorKind::InvalidMessageLength);
+    let compression = Compression::try_from(buf[4])?;
 
-    Ok((reader, compression))
-}
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    let data = decompress(&buf[size_of::<u32>() + 1..len], compression)?;
 
-#[async]
-fn decompress<R: AsyncRead + 'static>(reader: R, len: usize, compression: Compression) -> Result<(R, Vec<u8>)> {
-    let raw = vec![0; len];
-    let (reader, raw) = await!(io::read_exact(reader, raw))?;
+    debug!(len = data.len(), compression = compression.as_str(), "received message");
 
-    let decoded = match compression {
-        Compression::Off => raw,
+    if enabled!(Level::TRACE) {
+        for line in hexdump(&data).take(10) {
+            trace!("{}", line);
+        }
+    }
+
+    Ok(Some((data, len)))
+}
+
+fn decompress(raw: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    Ok(match compression {
+        Compression::Off => raw.to_vec(),
         Compression::Zlib => {
             let mut data = Vec::new();
-            zlib::Decoder::new(raw.as_slice())?.read_to_end(&mut data)?;
+            zlib::Decoder::new(raw)?.read_to_end(&mut data)?;
             data
         },
-    };
+        Compression::Zstd => zstd::decode_all(raw)?,
+    })
+}
+
+/// Formats `data` as lines of 16 bytes in hex and ASCII.
+fn hexdump(data: &[u8]) -> impl Iterator<Item = String> + '_ {
+    data.chunks(16).enumerate().map(|(i, chunk)| {
+        let hex = chunk.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ");
+        let ascii = chunk.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect::<String>();
+
+        format!("{:08x}  {:<47}  |{}|", i * 16, hex, ascii)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
 
-    Ok((reader, decoded))
+    #[test]
+    fn encode_message_uncompressed() {
+        let message = encode_message("_pong", &[Object::str("hi")], Compression::Off).unwrap();
+        assert_eq!(message, &b"\0\0\0\x17\0\0\0\0\x05_pongstr\0\0\0\x02hi"[..]);
+    }
+
+    #[test]
+    fn encode_message_compressed() {
+        for &compression in &[Compression::Zlib, Compression::Zstd] {
+            let message = encode_message("42", &[Object::int(7)], compression).unwrap();
+            assert_eq!(message[4], compression as u8);
+
+            let (data, len) = decode_message(&message).unwrap().unwrap();
+            assert_eq!(len, message.len());
+            assert_eq!(data, &b"\0\0\0\x0242int\0\0\0\x07"[..]);
+        }
+    }
+
+    #[test]
+    fn decode_partial_message() {
+        let mut buf = encode_message("_pong", &[Object::str("hi")], Compression::Off).unwrap();
+        let len = buf.len();
+        // The start of the next message
+        buf.extend_from_slice(b"\0\0\0");
+
+        for end in 0..len {
+            assert!(decode_message(&buf[..end]).unwrap().is_none());
+        }
+
+        let (data, consumed) = decode_message(&buf).unwrap().unwrap();
+        assert_eq!(consumed, len);
+        assert_eq!(data, &b"\0\0\0\x05_pongstr\0\0\0\x02hi"[..]);
+    }
+
+    #[test]
+    fn decode_invalid_length() {
+        assert!(decode_message(b"\0\0\0\x03\0").is_err());
+    }
 }
@@ -0,0 +1,437 @@
+//! A client that transparently reconnects when the connection to the relay is lost.
+
+use futures::prelude::*;
+use futures::channel::mpsc::{unbounded,UnboundedSender,UnboundedReceiver};
+use std::cmp;
+use std::collections::{BTreeMap,HashSet};
+use std::pin::Pin;
+use std::sync::{Arc,Mutex,Weak};
+use std::task::{Context,Poll};
+use std::time::Duration;
+use crate::address::Address;
+use crate::auth::AuthOptions;
+use crate::client::{Client,HDataRequest,Promise,SyncOption,BufferId};
+use crate::command::{self,Command};
+use crate::completion::Completion;
+use crate::event::Event;
+use crate::nicklist::Nicklist;
+use crate::object;
+use crate::errors::*;
+
+/// How long to wait between reconnection attempts.
+///
+/// The delay starts at `initial` and doubles after every failed attempt, up to `max`.
+/// An attempt that takes longer than `max` is given up.
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    /// The shortest delay, so a relay that is down isn't hammered with attempts.
+    const MIN_DELAY: Duration = Duration::from_millis(1);
+
+    /// `initial` is raised to 1ms and `max` to `initial` if they are shorter.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        let initial = cmp::max(initial, Self::MIN_DELAY);
+
+        Backoff {
+            initial,
+            max: cmp::max(max, initial),
+        }
+    }
+}
+
+impl Default for Backoff {
+    /// Starts at one second and waits at most one minute.
+    fn default() -> Self {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+/// A `Client` that reconnects to the relay when the connection is lost.
+///
+/// Requests that are pending when the connection drops,
+/// or that are made while reconnecting, fail with `ErrorKind::Disconnected`.
+/// After reconnecting, the buffers are synced again as they were
+/// and the event streams returned by `events` keep receiving events.
+pub struct ReconnectingClient {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    client: Option<Client>,
+    /// The synced buffers, with `*` for all buffers, like the relay keeps them.
+    subscriptions: HashSet<(String, SyncOption)>,
+    event_subscribers: Vec<UnboundedSender<Event>>,
+}
+
+/// The options the relay syncs if none are given.
+const ALL_SYNC_OPTIONS: &[SyncOption] = &[SyncOption::Buffers, SyncOption::Upgrade, SyncOption::Buffer, SyncOption::Nicklist];
+
+impl ReconnectingClient {
+    /// Connects and authenticates to the relay at `address`.
+    ///
+    /// Fails if the first connection attempt fails,
+    /// later attempts are retried according to `backoff`.
+    ///
+    /// A TOTP has to be given as `Totp::Secret`, since a `Totp::Code` expires
+    /// and would be rejected when reconnecting.
+    pub async fn connect<A: Into<AuthOptions>>(address: Address, options: A, backoff: Backoff) -> Result<Self> {
+        let options = options.into();
+        ensure!(!options.uses_totp_code(), "reconnecting requires a TOTP secret instead of a code");
+
+        let client = connect_client(address.clone(), options.clone()).await?;
+
+        let shared = Arc::new(Mutex::new(Shared {
+            client: None,
+            subscriptions: HashSet::new(),
+            event_subscribers: Vec::new(),
+        }));
+
+        attach(&shared, client);
+        tokio::spawn(supervise(address, options, backoff, Arc::downgrade(&shared)));
+
+        Ok(ReconnectingClient { shared })
+    }
+
+    /// Whether the client is currently connected to the relay.
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock().unwrap().client.is_some()
+    }
+
+    pub fn ping<S: Into<String>>(&self, msg: S) -> Pending<Promise<String>> {
+        self.request(|client| client.ping(msg))
+    }
+
+    pub fn info<S: Into<String>>(&self, name: S) -> Pending<Promise<object::Info>> {
+        self.request(|client| client.info(name))
+    }
+
+    pub fn infolist<S: Into<String>>(&self, name: S) -> Pending<Promise<object::InfoList>> {
+        self.request(|client| client.infolist(name))
+    }
+
+    /// See `Client::hdata`.
+    pub fn hdata<S: Into<String>>(&self, path: S) -> Pending<HDataRequest> {
+        self.request(|client| client.hdata(path))
+    }
+
+    /// See `Client::sync`. The buffers are synced again after reconnecting.
+    pub fn sync<S: AsRef<str>>(&self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let buffers = buffers.iter().map(|buffer| buffer.as_ref().to_owned()).collect::<Vec<_>>();
+        // Also checked while disconnected, so invalid buffer names are never replayed
+        Command::from(command::Sync(buffers.clone(), options.to_vec())).check()?;
+
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(ref client) = shared.client {
+            client.sync(&buffers, options)?;
+        }
+
+        shared.subscriptions.extend(subscriptions(&buffers, options));
+        Ok(())
+    }
+
+    /// See `Client::desync`. The buffers are no longer synced after reconnecting.
+    pub fn desync<S: AsRef<str>>(&self, buffers: &[S], options: &[SyncOption]) -> Result<()> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(ref client) = shared.client {
+            client.desync(buffers, options)?;
+        }
+
+        for subscription in subscriptions(buffers, options) {
+            shared.subscriptions.remove(&subscription);
+        }
+
+        Ok(())
+    }
+
+    /// See `Client::input`. Fails if the client is currently disconnected.
+    pub fn input<B: Into<BufferId>, S: Into<String>>(&self, buffer: B, text: S) -> Result<()> {
+        match self.shared.lock().unwrap().client {
+            Some(ref client) => client.input(buffer, text),
+            None => bail!(ErrorKind::Disconnected),
+        }
+    }
+
+    pub fn completion<B: Into<BufferId>, S: Into<String>>(&self, buffer: B, position: i32, data: S) -> Pending<Promise<Option<Completion>>> {
+        self.request(|client| client.completion(buffer, position, data))
+    }
+
+    pub fn nicklist(&self, buffer: Option<BufferId>) -> Pending<Promise<Nicklist>> {
+        self.request(|client| client.nicklist(buffer))
+    }
+
+    /// Returns a stream of all events received from now on, across reconnects.
+    pub fn events(&self) -> UnboundedReceiver<Event> {
+        let (tx, rx) = unbounded();
+        self.shared.lock().unwrap().event_subscribers.push(tx);
+        rx
+    }
+
+    fn request<F, R>(&self, request: R) -> Pending<F> where
+        R: FnOnce(&Client) -> F,
+    {
+        Pending {
+            inner: self.shared.lock().unwrap().client.as_ref().map(request),
+        }
+    }
+}
+
+/// A request made through a `ReconnectingClient`.
+///
+/// Fails with `ErrorKind::Disconnected` if the client was disconnected when the request was made,
+/// or if the connection is lost before the response arrives.
+#[must_use = "futures do nothing unless polled"]
+pub struct Pending<F> {
+    inner: Option<F>,
+}
+
+impl Pending<HDataRequest> {
+    /// See `HDataRequest::keys`.
+    pub fn keys<S: AsRef<str>>(self, keys: &[S]) -> Self {
+        Pending {
+            inner: self.inner.map(|request| request.keys(keys)),
+        }
+    }
+}
+
+impl<T, F: Future<Output = Result<T>> + Unpin> Future for Pending<F> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.inner {
+            Some(ref mut inner) => Pin::new(inner).poll(cx),
+            None => Poll::Ready(Err(ErrorKind::Disconnected.into())),
+        }
+    }
+}
+
+async fn connect_client(address: Address, options: AuthOptions) -> Result<Client> {
+    let stream = address.connect().await?;
+
+    Client::auth(stream, options).await
+}
+
+/// Makes `client` the current client, replays the subscriptions and forwards its events.
+fn attach(shared: &Arc<Mutex<Shared>>, client: Client) {
+    let weak = Arc::downgrade(shared);
+    let events = client.events().for_each(move |event| {
+        if let Some(shared) = weak.upgrade() {
+            shared.lock().unwrap().event_subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+        }
+        future::ready(())
+    });
+
+    tokio::spawn(events);
+
+    let mut shared = shared.lock().unwrap();
+
+    // Sorted so the commands are sent in the same order every time
+    let mut synced = BTreeMap::new();
+    for &(ref buffer, option) in &shared.subscriptions {
+        synced.entry(buffer.as_str()).or_insert_with(Vec::new).push(option);
+    }
+
+    for (buffer, mut options) in synced {
+        options.sort_by_key(SyncOption::as_str);
+        // The buffer names were checked by `ReconnectingClient::sync`
+        let _ = client.sync(&[buffer], &options);
+    }
+
+    shared.client = Some(client);
+}
+
+/// Splits the arguments of `sync` and `desync` into one subscription per buffer and option.
+fn subscriptions<S: AsRef<str>>(buffers: &[S], options: &[SyncOption]) -> Vec<(String, SyncOption)> {
+    let buffers = match buffers.len() {
+        0 => vec!["*"],
+        _ => buffers.iter().map(AsRef::as_ref).collect(),
+    };
+    let options = if options.is_empty() { ALL_SYNC_OPTIONS } else { options };
+
+    buffers.iter()
+        .flat_map(|&buffer| options.iter().map(move |&option| (buffer.to_owned(), option)))
+        .collect()
+}
+
+/// Waits for the current client to disconnect and reconnects,
+/// until the `ReconnectingClient` is dropped.
+async fn supervise(address: Address, options: AuthOptions, backoff: Backoff, shared: Weak<Mutex<Shared>>) {
+    loop {
+        let closed = match shared.upgrade() {
+            Some(shared) => match shared.lock().unwrap().client {
+                Some(ref client) => client.closed(),
+                None => return,
+            },
+            None => return,
+        };
+
+        let _ = closed.await;
+
+        match shared.upgrade() {
+            Some(shared) => shared.lock().unwrap().client = None,
+            None => return,
+        }
+
+        let mut delay = backoff.initial;
+
+        loop {
+            tokio::time::sleep(delay).await;
+
+            // A relay that accepts the connection but never answers mustn't stall the loop
+            let attempt = tokio::time::timeout(backoff.max, connect_client(address.clone(), options.clone()));
+
+            if let Ok(Ok(client)) = attempt.await {
+                match shared.upgrade() {
+                    Some(shared) => attach(&shared, client),
+                    None => return,
+                }
+                break;
+            }
+
+            delay = cmp::min(delay * 2, backoff.max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use crate::testing::{MockRelay,Request,Response};
+    use crate::auth::Totp;
+    use crate::object::Object;
+
+    async fn next_command(requests: &mut UnboundedReceiver<Request>, command: &str) -> Request {
+        loop {
+            let request = requests.next().await.unwrap();
+            if request.command == command {
+                return request;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect() {
+        let relay = MockRelay::new("secret")
+            .on("infolist", |req| vec![Response::reply(req, vec![Object::info_list((req.args.as_str(), vec![]))])]);
+        let mut requests = relay.requests();
+        let addr = relay.listen().await.unwrap();
+        let address = Address::Tcp { host: addr.ip().to_string(), port: addr.port() };
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+
+        let client = ReconnectingClient::connect(address, "secret", backoff).await.unwrap();
+        client.sync(&["irc.libera.#rust"], &[SyncOption::Nicklist, SyncOption::Buffer]).unwrap();
+        client.sync(&["core.weechat"], &[]).unwrap();
+        client.desync(&["core.weechat"], &[SyncOption::Upgrade, SyncOption::Buffers]).unwrap();
+        assert!(client.sync(&["core.weechat\nquit"], &[]).is_err());
+
+        // Never answered, so it is still pending when the relay goes away
+        let info = client.info("version");
+        next_command(&mut requests, "info").await;
+        relay.disconnect();
+
+        match info.await {
+            Err(Error(ErrorKind::Disconnected, _)) => {},
+            other => panic!("expected disconnect, got {:?}", other.map(|_| ())),
+        }
+
+        // Only what is still synced is replayed, one buffer at a time
+        assert_eq!(next_command(&mut requests, "init").await.command, "init");
+        assert_eq!(next_command(&mut requests, "sync").await.args, "core.weechat buffer,nicklist");
+        assert_eq!(next_command(&mut requests, "sync").await.args, "irc.libera.#rust buffer,nicklist");
+
+        let infolist = client.infolist("buffer").await.unwrap();
+        assert_eq!(infolist.name(), "buffer");
+    }
+
+    #[tokio::test]
+    async fn unresponsive_relay() {
+        let relay = MockRelay::new("secret");
+        let mut requests = relay.requests();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = relay.clone();
+
+        tokio::spawn(async move {
+            let mut unanswered = Vec::new();
+
+            for attempt in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+
+                // The first reconnection attempt is accepted but never answered
+                if attempt == 1 {
+                    unanswered.push(stream);
+                } else {
+                    tokio::spawn(served.clone().serve(stream));
+                }
+            }
+        });
+
+        let address = Address::Tcp { host: addr.ip().to_string(), port: addr.port() };
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100));
+        let client = ReconnectingClient::connect(address, "secret", backoff).await.unwrap();
+        next_command(&mut requests, "init").await;
+        relay.disconnect();
+
+        next_command(&mut requests, "init").await;
+
+        // Wait until the supervisor finished authenticating
+        while client.shared.lock().unwrap().client.is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(client.ping("hi").await.unwrap(), "hi");
+    }
+
+    #[test]
+    fn backoff() {
+        let backoff = Backoff::new(Duration::from_secs(0), Duration::from_secs(0));
+
+        assert_eq!(backoff.initial, Duration::from_millis(1));
+        assert_eq!(backoff.max, Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn disconnected() {
+        let relay = MockRelay::new("secret");
+        let addr = relay.listen().await.unwrap();
+        let address = Address::Tcp { host: addr.ip().to_string(), port: addr.port() };
+        // Long enough that the client stays disconnected during the test
+        let backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(60));
+
+        let client = ReconnectingClient::connect(address, "secret", backoff).await.unwrap();
+        let mut requests = relay.requests();
+        let info = client.info("version");
+        next_command(&mut requests, "info").await;
+        relay.disconnect();
+        assert!(info.await.is_err());
+
+        // Wait until the supervisor noticed the disconnect
+        while client.shared.lock().unwrap().client.is_some() {
+            tokio::task::yield_now().await;
+        }
+
+        match client.info("version").await {
+            Err(Error(ErrorKind::Disconnected, _)) => {},
+            other => panic!("expected disconnect, got {:?}", other.map(|_| ())),
+        }
+
+        match client.input("core.weechat", "hello") {
+            Err(Error(ErrorKind::Disconnected, _)) => {},
+            other => panic!("expected disconnect, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn totp_code() {
+        let address = Address::Tcp { host: "127.0.0.1".into(), port: 9001 };
+        let options = AuthOptions::new("secret").totp(Totp::Code("123456".into()));
+
+        assert!(ReconnectingClient::connect(address, options, Backoff::default()).await.is_err());
+    }
+}
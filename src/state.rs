@@ -0,0 +1,593 @@
+//! A local mirror of the buffers of WeeChat.
+//!
+//! Fetch the buffers once with `Client::fetch_state`, sync with `SyncOption::Buffers`
+//! (and `SyncOption::Buffer` for lines) and pass every event to `State::apply`
+//! to keep the mirror up to date.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use crate::event::Event;
+use crate::lines::{Lines,Line};
+use crate::object::{HData,HDataItem,Pointer};
+use crate::errors::*;
+
+/// The buffers of WeeChat, ordered by number.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct State {
+    buffers: Vec<Buffer>,
+}
+
+/// A change of the `State` caused by an event.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Change {
+    Opened(Pointer),
+    /// The buffer was closed and removed from the state.
+    Closed(Buffer),
+    /// The buffer got a new number, which may also change the numbers of other buffers.
+    Moved(Pointer),
+    Merged(Pointer),
+    Unmerged(Pointer),
+    Hidden(Pointer),
+    Unhidden(Pointer),
+    /// The full or short name of the buffer changed.
+    Renamed(Pointer),
+    TitleChanged(Pointer),
+    TypeChanged(Pointer),
+    LocalVariablesChanged(Pointer),
+    /// A line was added to the buffer.
+    LineAdded(Pointer),
+    /// All lines of the buffer were removed.
+    Cleared(Pointer),
+}
+
+impl State {
+    /// The hdata path to request the buffers from.
+    pub const HDATA_PATH: &'static str = "buffer:gui_buffers(*)";
+    /// The keys needed to build the buffers.
+    pub const HDATA_KEYS: &'static [&'static str] = &[
+        "number", "full_name", "short_name", "title", "type", "local_variables", "hidden",
+    ];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All buffers, ordered by number.
+    ///
+    /// Merged buffers share the same number.
+    pub fn buffers(&self) -> &[Buffer] {
+        &self.buffers
+    }
+
+    pub fn buffer(&self, pointer: &Pointer) -> Option<&Buffer> {
+        self.buffers.iter().find(|buffer| buffer.pointer == *pointer)
+    }
+
+    /// Returns the buffer with the full name `full_name`, e.g. `irc.libera.#rust`.
+    pub fn buffer_by_name(&self, full_name: &str) -> Option<&Buffer> {
+        self.buffers.iter().find(|buffer| buffer.full_name == full_name)
+    }
+
+    /// Replaces all buffers with the ones in `hdata`,
+    /// as received in response to requesting `HDATA_PATH`.
+    ///
+    /// The lines of buffers that are still open are kept.
+    pub fn update(&mut self, hdata: &HData) -> Result<()> {
+        let mut buffers = hdata.items().iter()
+            .map(Buffer::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        for buffer in &mut buffers {
+            if let Some(old) = self.buffers.iter_mut().find(|old| old.pointer == buffer.pointer) {
+                buffer.lines = ::std::mem::replace(&mut old.lines, Lines::new());
+            }
+        }
+
+        self.buffers = buffers;
+        self.sort();
+
+        Ok(())
+    }
+
+    /// Merges lines fetched with `Client::fetch_backlog` into the lines of `buffer`.
+    ///
+    /// Returns the number of new lines.
+    pub fn merge_backlog(&mut self, buffer: &Pointer, backlog: Vec<Line>) -> Result<usize> {
+        let index = self.index(buffer)?;
+        Ok(self.buffers[index].lines.merge(backlog))
+    }
+
+    /// Applies the changes of a buffer event and returns what changed.
+    ///
+    /// Events that don't affect buffers, e.g. nicklists, are ignored.
+    pub fn apply(&mut self, event: &Event) -> Result<Vec<Change>> {
+        if let Event::BufferLineAdded(ref hdata) = *event {
+            let mut changes = Vec::new();
+
+            for item in hdata.items() {
+                let line = Line::try_from(item)?;
+                let buffer = line.buffer().clone();
+                // The buffer may have been closed before the line arrived
+                let index = match self.index(&buffer) {
+                    Ok(index) => index,
+                    Err(_) => {
+                        debug!(%buffer, "line added to unknown buffer, skipping");
+                        continue;
+                    },
+                };
+
+                if self.buffers[index].lines.push(line) {
+                    changes.push(Change::LineAdded(buffer));
+                }
+            }
+
+            return Ok(changes);
+        }
+
+        let hdata = match *event {
+            Event::BufferOpened(ref hdata)
+            | Event::BufferTypeChanged(ref hdata)
+            | Event::BufferMoved(ref hdata)
+            | Event::BufferMerged(ref hdata)
+            | Event::BufferUnmerged(ref hdata)
+            | Event::BufferHidden(ref hdata)
+            | Event::BufferUnhidden(ref hdata)
+            | Event::BufferRenamed(ref hdata)
+            | Event::BufferTitleChanged(ref hdata)
+            | Event::BufferLocalVarAdded(ref hdata)
+            | Event::BufferLocalVarChanged(ref hdata)
+            | Event::BufferLocalVarRemoved(ref hdata)
+            | Event::BufferClosing(ref hdata)
+            | Event::BufferCleared(ref hdata) => hdata,
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut changes = Vec::with_capacity(hdata.items().len());
+
+        for item in hdata.items() {
+            let pointer = buffer_pointer(item)?.clone();
+
+            if let Event::BufferOpened(_) = *event {
+                self.open(item)?;
+                changes.push(Change::Opened(pointer));
+                continue;
+            }
+
+            if let Event::BufferClosing(_) = *event {
+                changes.extend(self.close(&pointer).map(Change::Closed));
+                continue;
+            }
+
+            let index = self.index(&pointer)?;
+
+            let change = match *event {
+                Event::BufferMoved(_) => {
+                    self.move_group(index, item.get_int("number")?);
+                    Change::Moved(pointer)
+                },
+                Event::BufferMerged(_) => {
+                    self.renumber(index, item.get_int("number")?, true);
+                    Change::Merged(pointer)
+                },
+                Event::BufferUnmerged(_) => {
+                    self.renumber(index, item.get_int("number")?, false);
+                    Change::Unmerged(pointer)
+                },
+                Event::BufferHidden(_) => {
+                    self.buffers[index].hidden = true;
+                    Change::Hidden(pointer)
+                },
+                Event::BufferUnhidden(_) => {
+                    self.buffers[index].hidden = false;
+                    Change::Unhidden(pointer)
+                },
+                Event::BufferRenamed(_) => {
+                    self.buffers[index].update(item)?;
+                    Change::Renamed(pointer)
+                },
+                Event::BufferTitleChanged(_) => {
+                    self.buffers[index].update(item)?;
+                    Change::TitleChanged(pointer)
+                },
+                Event::BufferTypeChanged(_) => {
+                    self.buffers[index].update(item)?;
+                    Change::TypeChanged(pointer)
+                },
+                Event::BufferCleared(_) => {
+                    self.buffers[index].lines.clear();
+                    Change::Cleared(pointer)
+                },
+                _ => {
+                    self.buffers[index].update(item)?;
+                    Change::LocalVariablesChanged(pointer)
+                },
+            };
+
+            changes.push(change);
+        }
+
+        self.sort();
+
+        Ok(changes)
+    }
+
+    fn index(&self, pointer: &Pointer) -> Result<usize> {
+        self.buffers.iter()
+            .position(|buffer| buffer.pointer == *pointer)
+            .ok_or_else(|| format!("unknown buffer {}", pointer).into())
+    }
+
+    fn open(&mut self, item: &HDataItem) -> Result<()> {
+        let buffer = Buffer::try_from(item)?;
+
+        // A buffer opened with the number of the previous buffer was merged into it
+        let merged = item.get_pointer("prev_buffer").ok()
+            .and_then(|prev| self.buffer(prev))
+            .is_some_and(|prev| prev.number == buffer.number);
+
+        if !merged {
+            self.shift(buffer.number, 1);
+        }
+
+        self.buffers.push(buffer);
+
+        Ok(())
+    }
+
+    fn close(&mut self, pointer: &Pointer) -> Option<Buffer> {
+        let index = self.buffers.iter().position(|buffer| buffer.pointer == *pointer)?;
+        let buffer = self.buffers.remove(index);
+
+        if !self.buffers.iter().any(|other| other.number == buffer.number) {
+            self.shift(buffer.number + 1, -1);
+        }
+
+        Some(buffer)
+    }
+
+    /// Moves the buffer at `index` and all buffers merged with it to `number`.
+    fn move_group(&mut self, index: usize, number: i32) {
+        let old = self.buffers[index].number;
+        let (group, others): (Vec<_>, Vec<_>) = self.buffers.drain(..)
+            .partition(|buffer| buffer.number == old);
+
+        self.buffers = others;
+        self.shift(old + 1, -1);
+        self.shift(number, 1);
+
+        self.buffers.extend(group.into_iter().map(|mut buffer| {
+            buffer.number = number;
+            buffer
+        }));
+    }
+
+    /// Gives the buffer at `index` the number `number`,
+    /// either merged with the buffers already there or as a new number.
+    fn renumber(&mut self, index: usize, number: i32, merged: bool) {
+        let mut buffer = self.buffers.remove(index);
+
+        if !self.buffers.iter().any(|other| other.number == buffer.number) {
+            self.shift(buffer.number + 1, -1);
+        }
+
+        if !merged {
+            self.shift(number, 1);
+        }
+
+        buffer.number = number;
+        self.buffers.push(buffer);
+    }
+
+    /// Adds `offset` to the numbers of all buffers starting at `number`.
+    fn shift(&mut self, number: i32, offset: i32) {
+        for buffer in &mut self.buffers {
+            if buffer.number >= number {
+                buffer.number += offset;
+            }
+        }
+    }
+
+    fn sort(&mut self) {
+        self.buffers.sort_by_key(|buffer| buffer.number);
+    }
+}
+
+impl TryFrom<&HData> for State {
+    type Error = Error;
+
+    fn try_from(hdata: &HData) -> Result<Self> {
+        let mut state = State::new();
+        state.update(hdata)?;
+        Ok(state)
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Buffer {
+    pointer: Pointer,
+    number: i32,
+    full_name: String,
+    short_name: String,
+    title: String,
+    buffer_type: BufferType,
+    local_variables: HashMap<String, String>,
+    hidden: bool,
+    lines: Lines,
+}
+
+/// Whether a buffer contains lines or free content.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum BufferType {
+    Formatted,
+    Free,
+}
+
+impl Buffer {
+    pub fn pointer(&self) -> &Pointer {
+        &self.pointer
+    }
+
+    pub fn number(&self) -> i32 {
+        self.number
+    }
+
+    /// The unique name of the buffer, e.g. `irc.libera.#rust`.
+    pub fn full_name(&self) -> &str {
+        &self.full_name
+    }
+
+    /// The name displayed in the buffer list, e.g. `#rust`.
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn buffer_type(&self) -> BufferType {
+        self.buffer_type
+    }
+
+    pub fn local_variables(&self) -> &HashMap<String, String> {
+        &self.local_variables
+    }
+
+    /// Returns the local variable `name`, e.g. `type`, `server` or `channel`.
+    pub fn local_variable(&self, name: &str) -> Option<&str> {
+        self.local_variables.get(name).map(String::as_str)
+    }
+
+    pub fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// The lines received so far, see `Client::fetch_backlog` for older ones.
+    pub fn lines(&self) -> &Lines {
+        &self.lines
+    }
+
+    /// Updates the fields contained in `item`, since events only send what changed.
+    fn update(&mut self, item: &HDataItem) -> Result<()> {
+        if item.get("number").is_some() {
+            self.number = item.get_int("number")?;
+        }
+
+        if item.get("full_name").is_some() {
+            self.full_name = item.get_str("full_name")?.into();
+        }
+
+        if item.get("short_name").is_some() {
+            self.short_name = item.get_str("short_name")?.into();
+        }
+
+        if item.get("title").is_some() {
+            self.title = item.get_str("title")?.into();
+        }
+
+        if item.get("type").is_some() {
+            self.buffer_type = match item.get_int("type")? {
+                0 => BufferType::Formatted,
+                1 => BufferType::Free,
+                other => bail!("unknown buffer type {}", other),
+            };
+        }
+
+        if item.get("local_variables").is_some() {
+            self.local_variables = item.get_hash_table("local_variables")?.iter()
+                .filter_map(|(name, value)| Some((name.as_str()?.to_owned(), value.as_str()?.to_owned())))
+                .collect();
+        }
+
+        if item.get("hidden").is_some() {
+            self.hidden = item.get_int("hidden")? != 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&HDataItem> for Buffer {
+    type Error = Error;
+
+    fn try_from(item: &HDataItem) -> Result<Self> {
+        let mut buffer = Buffer {
+            pointer: buffer_pointer(item)?.clone(),
+            number: item.get_int("number")?,
+            full_name: item.get_str("full_name")?.into(),
+            short_name: String::new(),
+            title: String::new(),
+            buffer_type: BufferType::Formatted,
+            local_variables: HashMap::new(),
+            hidden: false,
+            lines: Lines::new(),
+        };
+
+        buffer.update(item)?;
+
+        Ok(buffer)
+    }
+}
+
+fn buffer_pointer(item: &HDataItem) -> Result<&Pointer> {
+    item.pointers().first().ok_or_else(|| "missing buffer pointer".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Object,HashTable};
+
+    fn item(pointer: usize, number: i32, full_name: &str) -> HDataItem {
+        item_with(pointer, number, full_name, Vec::new())
+    }
+
+    fn item_with(pointer: usize, number: i32, full_name: &str, values: Vec<(&str, Object)>) -> HDataItem {
+        let mut values = values.into_iter()
+            .map(|(key, value)| (Object::str(key), value))
+            .collect::<HashMap<_,_>>();
+        values.insert(Object::str("number"), Object::int(number));
+        values.insert(Object::str("full_name"), Object::str(full_name));
+
+        HDataItem::new(vec![Pointer::from(pointer)], values)
+    }
+
+    fn hdata(items: Vec<HDataItem>) -> HData {
+        HData::new("buffer", Vec::new(), items)
+    }
+
+    fn numbers(state: &State) -> Vec<(&str, i32)> {
+        state.buffers().iter().map(|buffer| (buffer.full_name(), buffer.number())).collect()
+    }
+
+    fn state() -> State {
+        State::try_from(&hdata(vec![
+            item(0x1, 1, "core.weechat"),
+            item(0x2, 2, "irc.server.libera"),
+            item(0x3, 3, "irc.libera.#rust"),
+            item(0x4, 4, "irc.libera.#weechat"),
+        ])).unwrap()
+    }
+
+    #[test]
+    fn update() {
+        let item = item_with(0x1, 1, "core.weechat", vec![
+            ("short_name", Object::str("weechat")),
+            ("type", Object::int(0)),
+            ("hidden", Object::int(1)),
+            ("local_variables", Object::hash_table(HashTable::from(hashmap!{
+                Object::str("plugin") => Object::str("core"),
+            }))),
+        ]);
+
+        let state = State::try_from(&hdata(vec![item])).unwrap();
+        let buffer = state.buffer(&Pointer::from(0x1)).unwrap();
+
+        assert_eq!(buffer.short_name(), "weechat");
+        assert_eq!(buffer.buffer_type(), BufferType::Formatted);
+        assert!(buffer.hidden());
+        assert_eq!(buffer.local_variable("plugin"), Some("core"));
+    }
+
+    #[test]
+    fn open_and_close() {
+        let mut state = state();
+
+        let changes = state.apply(&Event::BufferOpened(hdata(vec![item(0x5, 3, "irc.libera.#new")]))).unwrap();
+        assert_eq!(changes, vec![Change::Opened(Pointer::from(0x5))]);
+        assert_eq!(numbers(&state), vec![
+            ("core.weechat", 1), ("irc.server.libera", 2), ("irc.libera.#new", 3),
+            ("irc.libera.#rust", 4), ("irc.libera.#weechat", 5),
+        ]);
+
+        let changes = state.apply(&Event::BufferClosing(hdata(vec![item(0x2, 2, "irc.server.libera")]))).unwrap();
+        assert!(match changes[..] { [Change::Closed(ref buffer)] => buffer.full_name() == "irc.server.libera", _ => false });
+        assert_eq!(numbers(&state), vec![
+            ("core.weechat", 1), ("irc.libera.#new", 2), ("irc.libera.#rust", 3), ("irc.libera.#weechat", 4),
+        ]);
+    }
+
+    #[test]
+    fn move_merge_unmerge() {
+        let mut state = state();
+
+        state.apply(&Event::BufferMoved(hdata(vec![item(0x4, 2, "irc.libera.#weechat")]))).unwrap();
+        assert_eq!(numbers(&state), vec![
+            ("core.weechat", 1), ("irc.libera.#weechat", 2), ("irc.server.libera", 3), ("irc.libera.#rust", 4),
+        ]);
+
+        state.apply(&Event::BufferMerged(hdata(vec![item(0x2, 1, "irc.server.libera")]))).unwrap();
+        assert_eq!(numbers(&state), vec![
+            ("core.weechat", 1), ("irc.server.libera", 1), ("irc.libera.#weechat", 2), ("irc.libera.#rust", 3),
+        ]);
+
+        // Merged buffers move together
+        state.apply(&Event::BufferMoved(hdata(vec![item(0x1, 3, "core.weechat")]))).unwrap();
+        assert_eq!(numbers(&state), vec![
+            ("irc.libera.#weechat", 1), ("irc.libera.#rust", 2), ("core.weechat", 3), ("irc.server.libera", 3),
+        ]);
+
+        state.apply(&Event::BufferUnmerged(hdata(vec![item(0x2, 4, "irc.server.libera")]))).unwrap();
+        assert_eq!(numbers(&state), vec![
+            ("irc.libera.#weechat", 1), ("irc.libera.#rust", 2), ("core.weechat", 3), ("irc.server.libera", 4),
+        ]);
+    }
+
+    #[test]
+    fn lines() {
+        let mut state = state();
+        let line = |pointer: usize, date: u64, message: &str| HDataItem::new(vec![Pointer::from(pointer)], hashmap!{
+            Object::str("buffer") => Object::pointer(0x3),
+            Object::str("date") => Object::time(date),
+            Object::str("date_printed") => Object::time(date),
+            Object::str("displayed") => Object::char(1),
+            Object::str("highlight") => Object::char(0),
+            Object::str("tags_array") => Object::array(Vec::new()),
+            Object::str("prefix") => Object::str("nick"),
+            Object::str("message") => Object::str(message),
+        });
+
+        // Lines of unknown buffers don't prevent the other lines from being added
+        let mut values = line(0x12, 1001, "closed").values().clone();
+        values.insert(Object::str("buffer"), Object::pointer(0x9));
+        let orphan = HDataItem::new(vec![Pointer::from(0x12)], values);
+
+        let items = vec![orphan, line(0x11, 1001, "b")];
+        let changes = state.apply(&Event::BufferLineAdded(HData::new("line_data", Vec::new(), items))).unwrap();
+        assert_eq!(changes, vec![Change::LineAdded(Pointer::from(0x3))]);
+
+        let backlog = [line(0x10, 1000, "a"), line(0x11, 1001, "b")].iter()
+            .map(Line::try_from)
+            .collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(state.merge_backlog(&Pointer::from(0x3), backlog).unwrap(), 1);
+
+        let buffer = state.buffer(&Pointer::from(0x3)).unwrap();
+        assert_eq!(buffer.lines().lines().iter().map(Line::message).collect::<Vec<_>>(), vec!["a", "b"]);
+
+        // Lines are kept when fetching the buffers again
+        state.update(&hdata(vec![item(0x3, 1, "irc.libera.#rust")])).unwrap();
+        assert_eq!(state.buffer(&Pointer::from(0x3)).unwrap().lines().len(), 2);
+
+        let changes = state.apply(&Event::BufferCleared(hdata(vec![item(0x3, 1, "irc.libera.#rust")]))).unwrap();
+        assert_eq!(changes, vec![Change::Cleared(Pointer::from(0x3))]);
+        assert!(state.buffer(&Pointer::from(0x3)).unwrap().lines().is_empty());
+    }
+
+    #[test]
+    fn rename_and_hide() {
+        let mut state = state();
+        let renamed = item_with(0x3, 3, "irc.libera.#rust-beginners", vec![
+            ("short_name", Object::str("#rust-beginners")),
+        ]);
+
+        let changes = state.apply(&Event::BufferRenamed(hdata(vec![renamed]))).unwrap();
+        assert_eq!(changes, vec![Change::Renamed(Pointer::from(0x3))]);
+        assert_eq!(state.buffer(&Pointer::from(0x3)).unwrap().short_name(), "#rust-beginners");
+        assert!(state.buffer_by_name("irc.libera.#rust-beginners").is_some());
+
+        state.apply(&Event::BufferHidden(hdata(vec![item(0x3, 3, "irc.libera.#rust-beginners")]))).unwrap();
+        assert!(state.buffer(&Pointer::from(0x3)).unwrap().hidden());
+
+        assert!(state.apply(&Event::BufferTitleChanged(hdata(vec![item(0x9, 9, "unknown")]))).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+//! An in-process WeeChat relay for testing code built on `Client`.
+//!
+//! ```ignore
+//! let relay = MockRelay::new("secret")
+//!     .on("info", |req| vec![Response::reply(req, vec![Object::info(("version", "4.0.0"))])]);
+//! let addr = relay.listen().await?;
+//! ```
+
+use futures::prelude::*;
+use futures::channel::mpsc::{unbounded,UnboundedSender,UnboundedReceiver};
+use futures::channel::oneshot;
+use futures::future::Either;
+use tokio::io::{AsyncRead,AsyncWrite,AsyncBufReadExt,AsyncWriteExt,BufReader};
+use tokio::net::TcpListener;
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::sync::{Arc,Mutex};
+use std::time::Duration;
+use crate::object::{Object,HashTable};
+use crate::raw::{Compression,encode_message};
+use crate::errors::*;
+
+/// A command received by the `MockRelay`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Request {
+    /// The id in parentheses in front of the command, if any.
+    pub id: Option<String>,
+    pub command: String,
+    /// Everything after the command name.
+    pub args: String,
+}
+
+impl Request {
+    fn parse(line: &str) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (id, line) = if line.starts_with('(') {
+            match line.find(')') {
+                Some(end) => (Some(line[1..end].to_owned()), line[end + 1..].trim_start()),
+                None => (None, line),
+            }
+        } else {
+            (None, line)
+        };
+
+        let mut parts = line.splitn(2, ' ');
+
+        Request {
+            id,
+            command: parts.next().unwrap_or("").into(),
+            args: parts.next().unwrap_or("").into(),
+        }
+    }
+}
+
+/// A message sent by the `MockRelay`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Response {
+    pub id: String,
+    pub objects: Vec<Object>,
+}
+
+impl Response {
+    pub fn new<S: Into<String>>(id: S, objects: Vec<Object>) -> Self {
+        Response {
+            id: id.into(),
+            objects,
+        }
+    }
+
+    /// Creates a response with the same id as `request`.
+    pub fn reply(request: &Request, objects: Vec<Object>) -> Self {
+        Response::new(request.id.clone().unwrap_or_default(), objects)
+    }
+}
+
+type Handler = Arc<dyn Fn(&Request) -> Vec<Response> + Send + Sync>;
+
+/// A scriptable stand-in for a WeeChat relay.
+///
+/// `handshake`, `init`, `ping` and `quit` are handled by the relay itself.
+/// Replies to all other commands have to be registered with `on`,
+/// commands without a handler are recorded but not answered.
+#[derive(Clone)]
+pub struct MockRelay {
+    password: String,
+    handshake: bool,
+    auth_delay: Duration,
+    handlers: Arc<Mutex<HashMap<String, Handler>>>,
+    received: Arc<Mutex<Vec<Request>>>,
+    request_subscribers: Arc<Mutex<Vec<UnboundedSender<Request>>>>,
+    connections: Arc<Mutex<Vec<UnboundedSender<Vec<u8>>>>>,
+    closers: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+}
+
+impl MockRelay {
+    pub fn new<P: Into<String>>(password: P) -> Self {
+        MockRelay {
+            password: password.into(),
+            handshake: true,
+            auth_delay: Duration::from_secs(0),
+            handlers: Arc::new(Mutex::new(HashMap::new())),
+            received: Arc::new(Mutex::new(Vec::new())),
+            request_subscribers: Arc::new(Mutex::new(Vec::new())),
+            connections: Arc::new(Mutex::new(Vec::new())),
+            closers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answers every `command` with the responses returned by `handler`.
+    pub fn on<S, F>(self, command: S, handler: F) -> Self where
+        S: Into<String>,
+        F: Fn(&Request) -> Vec<Response> + Send + Sync + 'static,
+    {
+        self.handlers.lock().unwrap().insert(command.into(), Arc::new(handler));
+        self
+    }
+
+    /// Ignores the `handshake` command like relays older than WeeChat 3.5.
+    pub fn without_handshake(mut self) -> Self {
+        self.handshake = false;
+        self
+    }
+
+    /// Waits `delay` after `init` before answering anything, like a relay busy hashing the password.
+    pub fn auth_delay(mut self, delay: Duration) -> Self {
+        self.auth_delay = delay;
+        self
+    }
+
+    /// Returns all commands received so far, across all connections.
+    pub fn received(&self) -> Vec<Request> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Returns a stream of all commands received from now on, across all connections.
+    ///
+    /// Useful for waiting until a client has sent a command.
+    pub fn requests(&self) -> UnboundedReceiver<Request> {
+        let (tx, rx) = unbounded();
+        self.request_subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Sends `response` to all connected clients, e.g. to simulate events.
+    pub fn push(&self, response: Response) -> Result<()> {
+        let message = encode_message(&response.id, &response.objects, Compression::Off)?;
+        self.connections.lock().unwrap().retain(|tx| tx.unbounded_send(message.clone()).is_ok());
+        Ok(())
+    }
+
+    /// Closes all current connections, e.g. to simulate a relay restart.
+    ///
+    /// New connections are still accepted.
+    pub fn disconnect(&self) {
+        self.connections.lock().unwrap().clear();
+
+        for tx in self.closers.lock().unwrap().drain(..) {
+            // Fails if the connection already ended on its own
+            let _ = tx.send(());
+        }
+    }
+
+    /// Accepts connections on a random local port.
+    pub async fn listen(&self) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let relay = self.clone();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(relay.clone().serve(stream));
+            }
+        });
+
+        Ok(addr)
+    }
+
+    /// Serves a single client connected through `stream`.
+    pub async fn serve<S: AsyncRead + AsyncWrite + Send + 'static>(self, stream: S) -> Result<()> {
+        let (reader, writer) = tokio::io::split(stream);
+        let (message_tx, message_rx) = unbounded();
+        let (close_tx, close_rx) = oneshot::channel();
+        self.connections.lock().unwrap().push(message_tx.clone());
+        self.closers.lock().unwrap().push(close_tx);
+
+        let sender = Box::pin(send_messages(writer, message_rx));
+        let receiver = Box::pin(self.receive_commands(reader, message_tx));
+        let served = future::select(sender, receiver).map(|either| either.factor_first().0);
+
+        match future::select(served, close_rx).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Ok(()),
+        }
+    }
+
+    async fn receive_commands<R: AsyncRead + Unpin>(self, reader: R, message_tx: UnboundedSender<Vec<u8>>) -> Result<()> {
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let request = Request::parse(&line);
+            self.received.lock().unwrap().push(request.clone());
+            self.request_subscribers.lock().unwrap().retain(|tx| tx.unbounded_send(request.clone()).is_ok());
+
+            let responses = match self.respond(&request) {
+                Some(responses) => responses,
+                None => break,
+            };
+
+            if request.command == "init" {
+                tokio::time::sleep(self.auth_delay).await;
+            }
+
+            for response in responses {
+                let message = encode_message(&response.id, &response.objects, Compression::Off)?;
+                message_tx.unbounded_send(message).map_err(|_| ErrorKind::Disconnected)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the responses to `request`, or `None` if the connection should be closed.
+    fn respond(&self, request: &Request) -> Option<Vec<Response>> {
+        match request.command.as_str() {
+            "handshake" if self.handshake => Some(vec![Response::reply(request, vec![self.handshake(request)])]),
+            "handshake" => Some(Vec::new()),
+            "init" => if self.check_password(&request.args) { Some(Vec::new()) } else { None },
+            "ping" => Some(vec![Response::new("_pong", vec![Object::str(request.args.as_str())])]),
+            "quit" => None,
+            command => {
+                let handler = self.handlers.lock().unwrap().get(command).cloned();
+                Some(handler.map(|handler| (*handler)(request)).unwrap_or_default())
+            },
+        }
+    }
+
+    /// Only supports plain passwords, so every other algorithm is refused.
+    fn handshake(&self, request: &Request) -> Object {
+        let algos = find_arg(&request.args, "password_hash_algo").unwrap_or_default();
+        let algo = if algos.split(':').any(|algo| algo == "plain") { "plain" } else { "" };
+
+        Object::hash_table(HashTable::from(hashmap_of(&[
+            ("password_hash_algo", algo),
+            ("password_hash_iterations", "100000"),
+            ("totp", "off"),
+            ("nonce", "85b1ee00695a5b254e14f4885538df0d"),
+            ("compression", "off"),
+        ])))
+    }
+
+    fn check_password(&self, args: &str) -> bool {
+        find_arg(args, "password").is_some_and(|password| password == self.password)
+    }
+}
+
+async fn send_messages<W: AsyncWrite + Unpin>(mut writer: W, mut message_rx: UnboundedReceiver<Vec<u8>>) -> Result<()> {
+    while let Some(message) = message_rx.next().await {
+        writer.write_all(&message).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the value of the `name=value` pair in `args`.
+fn find_arg(args: &str, name: &str) -> Option<String> {
+    split_args(args).into_iter()
+        .filter_map(|arg| {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => Some(value.to_owned()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Splits arguments at unescaped commas and unescapes them.
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = vec![String::new()];
+    let mut chars = args.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => result.last_mut().unwrap().extend(chars.next()),
+            ',' => result.push(String::new()),
+            ch => result.last_mut().unwrap().push(ch),
+        }
+    }
+
+    result
+}
+
+fn hashmap_of(pairs: &[(&str, &str)]) -> HashMap<Object,Object> {
+    pairs.iter()
+        .map(|&(key, value)| (Object::str(key), Object::str(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+    use crate::client::Client;
+
+    async fn connect(relay: &MockRelay, password: &str) -> Result<Client> {
+        let addr = relay.listen().await?;
+        let stream = TcpStream::connect(addr).await?;
+
+        Client::auth(stream, password).await
+    }
+
+    #[test]
+    fn parse_request() {
+        assert_eq!(Request::parse("(42) info version\n"), Request {
+            id: Some("42".into()),
+            command: "info".into(),
+            args: "version".into(),
+        });
+        assert_eq!(Request::parse("ping"), Request {
+            id: None,
+            command: "ping".into(),
+            args: "".into(),
+        });
+    }
+
+    #[tokio::test]
+    async fn auth() {
+        let relay = MockRelay::new("sec,ret");
+        let client = connect(&relay, "sec,ret").await;
+
+        assert!(client.is_ok());
+        assert_eq!(relay.received().iter().map(|req| req.command.as_str()).collect::<Vec<_>>(), vec!["handshake", "init", "ping"]);
+    }
+
+    #[tokio::test]
+    async fn auth_failed() {
+        let relay = MockRelay::new("secret");
+        let client = connect(&relay, "wrong").await;
+
+        match client {
+            Err(Error(ErrorKind::AuthFailed, _)) => {},
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("authenticated with a wrong password"),
+        }
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let relay = MockRelay::new("secret");
+        let client = connect(&relay, "secret").await.unwrap();
+        let info = client.info("version");
+
+        relay.disconnect();
+
+        assert!(client.closed().await.is_err());
+        match info.await {
+            Err(Error(ErrorKind::Disconnected, _)) => {},
+            other => panic!("expected disconnect, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn info() {
+        let relay = MockRelay::new("secret")
+            .on("info", |req| vec![Response::reply(req, vec![Object::info((req.args.as_str(), "4.0.0"))])]);
+        let client = connect(&relay, "secret").await.unwrap();
+
+        let info = client.info("version").await.unwrap();
+
+        assert_eq!(info.name(), "version");
+        assert_eq!(info.value(), "4.0.0");
+    }
+}
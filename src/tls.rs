@@ -0,0 +1,247 @@
+//! Connecting to relays that use TLS (`ssl.weechat` in `relay.network`).
+
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use rustls::{ClientConfig,DigitallySignedStruct,RootCertStore,SignatureScheme};
+use rustls::client::danger::{HandshakeSignatureValid,ServerCertVerified,ServerCertVerifier};
+use rustls::crypto::{self,CryptoProvider};
+use rustls::pki_types::{CertificateDer,PrivateKeyDer,ServerName,UnixTime};
+use sha2::{Sha256,Digest};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead,BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use crate::transport::connect_tcp;
+use crate::auth::from_hex;
+use crate::errors::*;
+
+/// Configuration for TLS connections to the relay.
+///
+/// By default the relay certificate is verified against the Mozilla root certificates.
+pub struct TlsConfig {
+    ca_certs: Vec<CertificateDer<'static>>,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    fingerprints: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        TlsConfig {
+            ca_certs: Vec::new(),
+            client_cert: None,
+            fingerprints: Vec::new(),
+        }
+    }
+
+    /// Trusts the PEM encoded CA certificates in `path` in addition to the default roots.
+    pub fn ca_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        let file = File::open(path).chain_err(|| "opening CA file")?;
+        self.ca_pem(&mut BufReader::new(file))
+    }
+
+    /// Trusts the PEM encoded CA certificates read from `reader`.
+    pub fn ca_pem(mut self, reader: &mut dyn BufRead) -> Result<Self> {
+        let certs = rustls_pemfile::certs(reader).collect::<::std::result::Result<Vec<_>, _>>()
+            .chain_err(|| "invalid CA certificates")?;
+        self.ca_certs.extend(certs);
+        Ok(self)
+    }
+
+    /// Authenticates with the PEM encoded client certificate chain and private key.
+    pub fn client_cert_pem(mut self, certs: &mut dyn BufRead, key: &mut dyn BufRead) -> Result<Self> {
+        let certs = rustls_pemfile::certs(certs).collect::<::std::result::Result<Vec<_>, _>>()
+            .chain_err(|| "invalid client certificate")?;
+        let key = rustls_pemfile::private_key(key)
+            .chain_err(|| "invalid client private key")?
+            .ok_or("invalid client private key")?;
+
+        self.client_cert = Some((certs, key));
+        Ok(self)
+    }
+
+    /// Only accepts a relay certificate with the given SHA-256 fingerprint,
+    /// e.g. for self-signed certificates.
+    ///
+    /// The fingerprint is hex encoded and may contain colons.
+    /// If any fingerprint is pinned, the CA certificates are not consulted.
+    pub fn pin_fingerprint(mut self, fingerprint: &str) -> Result<Self> {
+        self.fingerprints.push(parse_fingerprint(fingerprint)?);
+        Ok(self)
+    }
+
+    fn client_config(&self) -> Result<ClientConfig> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .chain_err(|| "unsupported TLS protocol versions")?;
+
+        let builder = if self.fingerprints.is_empty() {
+            let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+
+            for cert in &self.ca_certs {
+                roots.add(cert.clone()).chain_err(|| "invalid CA certificate")?;
+            }
+
+            builder.with_root_certificates(roots)
+        } else {
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(FingerprintVerifier {
+                fingerprints: self.fingerprints.clone(),
+                provider,
+            }))
+        };
+
+        Ok(match self.client_cert {
+            Some((ref certs, ref key)) => builder.with_client_auth_cert(certs.clone(), key.clone_key())
+                .chain_err(|| "invalid client certificate")?,
+            None => builder.with_no_client_auth(),
+        })
+    }
+}
+
+impl Clone for TlsConfig {
+    fn clone(&self) -> Self {
+        TlsConfig {
+            ca_certs: self.ca_certs.clone(),
+            client_cert: self.client_cert.as_ref().map(|(certs, key)| (certs.clone(), key.clone_key())),
+            fingerprints: self.fingerprints.clone(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connects to the relay at `host` and `port` and performs the TLS handshake.
+///
+/// The returned stream can be passed to `Client::auth`.
+pub async fn connect_tls(host: String, port: u16, config: TlsConfig) -> Result<TlsStream<TcpStream>> {
+    let connector = TlsConnector::from(Arc::new(config.client_config()?));
+    let server_name = ServerName::try_from(host.clone()).chain_err(|| "invalid host name")?;
+
+    let stream = connect_tcp(host, port).await?;
+    let stream = connector.connect(server_name, stream).await.chain_err(|| "TLS handshake failed")?;
+
+    Ok(stream)
+}
+
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprints: Vec<Vec<u8>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> ::std::result::Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Sha256::digest(end_entity.as_ref());
+
+        if self.fingerprints.iter().any(|pinned| pinned.as_slice() == fingerprint.as_slice()) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("certificate fingerprint does not match".into()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> ::std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> ::std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>> {
+    let hex = fingerprint.replace(':', "");
+    let fingerprint = from_hex(&hex).chain_err(|| "invalid fingerprint")?;
+    ensure!(fingerprint.len() == 32, "SHA-256 fingerprint must be 32 bytes");
+
+    Ok(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::ServerConfig;
+    use rustls::pki_types::PrivatePkcs8KeyDer;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Accepts one TLS connection with a new self-signed certificate and returns its port and fingerprint.
+    async fn serve_self_signed() -> (u16, String) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+        let fingerprint = Sha256::digest(cert.as_ref()).iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":");
+
+        let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions().unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // Fails if the client rejected the certificate
+            let _ = acceptor.accept(stream).await;
+        });
+
+        (port, fingerprint)
+    }
+
+    #[test]
+    fn fingerprint() {
+        let colons = "AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89";
+        let fingerprint = parse_fingerprint(colons).unwrap();
+
+        assert_eq!(fingerprint.len(), 32);
+        assert_eq!(&fingerprint[..4], &[0xab, 0xcd, 0xef, 0x01]);
+        assert_eq!(parse_fingerprint(&colons.replace(":", "")).unwrap(), fingerprint);
+        assert!(parse_fingerprint("abcd").is_err());
+        assert!(parse_fingerprint(&"é".repeat(32)).is_err());
+        assert!(parse_fingerprint(&"g".repeat(64)).is_err());
+    }
+
+    #[tokio::test]
+    async fn pinned_fingerprint() {
+        let (port, fingerprint) = serve_self_signed().await;
+        let config = TlsConfig::new().pin_fingerprint(&fingerprint).unwrap();
+
+        assert!(connect_tls("127.0.0.1".into(), port, config).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn other_fingerprint() {
+        let (port, _) = serve_self_signed().await;
+        let config = TlsConfig::new().pin_fingerprint(&"AB".repeat(32)).unwrap();
+
+        assert!(connect_tls("127.0.0.1".into(), port, config).await.is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Time-based one-time passwords as specified in RFC 6238.
+
+use std::time::{SystemTime,UNIX_EPOCH};
+use byteorder::{ByteOrder,BigEndian};
+use hmac::{Hmac,Mac};
+use sha1::Sha1;
+use crate::errors::*;
+
+const TIME_STEP: u64 = 30;
+const DIGITS: u32 = 6;
+
+/// Generates the code for the current time from a base32 encoded `secret`.
+pub fn current_code(secret: &str) -> Result<String> {
+    let secret = decode_base32(secret).chain_err(|| "decoding TOTP secret")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .chain_err(|| "system time is before the unix epoch")?;
+
+    Ok(code(&secret, now.as_secs()))
+}
+
+/// Generates the code for the unix timestamp `time`.
+pub fn code(secret: &[u8], time: u64) -> String {
+    let mut counter = [0; 8];
+    BigEndian::write_u64(&mut counter, time / TIME_STEP);
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter);
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = BigEndian::read_u32(&hash[offset..offset + 4]) & 0x7fff_ffff;
+
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// Decodes RFC 4648 base32, ignoring case, whitespace and padding.
+fn decode_base32(input: &str) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for ch in input.chars().filter(|ch| !ch.is_whitespace() && *ch != '=') {
+        let value = match ch.to_ascii_uppercase() {
+            ch @ 'A' ..= 'Z' => ch as u32 - 'A' as u32,
+            ch @ '2' ..= '7' => ch as u32 - '2' as u32 + 26,
+            ch => bail!("invalid base32 character '{}'", ch),
+        };
+
+        buffer = (buffer << 5) | value;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc6238() {
+        assert_eq!(code(SECRET, 59), "287082");
+        assert_eq!(code(SECRET, 1111111109), "081804");
+        assert_eq!(code(SECRET, 1234567890), "005924");
+        assert_eq!(code(SECRET, 20000000000), "353130");
+    }
+
+    #[test]
+    fn base32() {
+        assert_eq!(decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(), SECRET);
+        assert_eq!(decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(), SECRET);
+        assert_eq!(decode_base32("MZXW6===").unwrap(), b"foo");
+        assert!(decode_base32("MZXW1").is_err());
+    }
+}
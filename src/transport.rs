@@ -0,0 +1,33 @@
+use tokio::io::{AsyncRead,AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use crate::errors::*;
+
+/// A bidirectional byte stream to the relay, as accepted by `Client::auth`.
+///
+/// Used to box the different kinds of connections.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// Connects to the relay at `host` and `port` without encryption.
+pub async fn connect_tcp(host: String, port: u16) -> Result<TcpStream> {
+    let stream = TcpStream::connect((host.as_str(), port)).await
+        .chain_err(|| format!("connecting to '{}:{}'", host, port))?;
+
+    Ok(stream)
+}
+
+/// Connects to a relay listening on the UNIX domain socket at `path`,
+/// as created by `/relay add unix.weechat <path>`.
+#[cfg(unix)]
+pub async fn connect_unix<P: AsRef<Path>>(path: P) -> Result<UnixStream> {
+    let path = path.as_ref();
+    let stream = UnixStream::connect(path).await
+        .chain_err(|| format!("connecting to '{}'", path.display()))?;
+
+    Ok(stream)
+}
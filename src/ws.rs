@@ -0,0 +1,180 @@
+//! Connecting to relays through the WebSocket endpoint `/weechat`,
+//! e.g. behind a reverse proxy.
+
+use futures::prelude::*;
+use futures::ready;
+use tokio::io::{AsyncRead,AsyncWrite,ReadBuf};
+use tokio_tungstenite::{client_async,WebSocketStream};
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context,Poll};
+use crate::transport::{Transport,connect_tcp};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConfig,connect_tls};
+use crate::errors::*;
+
+/// Adapts a WebSocket connection to the byte stream expected by `Client::auth`.
+///
+/// Messages of the relay are received as binary frames,
+/// every line written is sent as a text frame.
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        WsStream {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Sends all complete lines that have been written so far.
+    fn poll_send_lines(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while let Some(end) = self.write_buf.iter().position(|&byte| byte == b'\n') {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io_error)?;
+
+            let line = self.write_buf.drain(..end + 1).collect::<Vec<_>>();
+            let line = String::from_utf8(line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            Pin::new(&mut self.inner).start_send(Message::text(line)).map_err(to_io_error)?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.read_pos < this.read_buf.len() {
+                let data = &this.read_buf[this.read_pos..];
+                let n = data.len().min(buf.remaining());
+                buf.put_slice(&data[..n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    this.read_buf = data.into();
+                    this.read_pos = 0;
+                },
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Ok(_)) => {},
+                Some(Err(err)) => return Poll::Ready(Err(to_io_error(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Only buffer more data once the previous lines are on their way
+        ready!(this.poll_send_lines(cx))?;
+        this.write_buf.extend_from_slice(buf);
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_send_lines(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx).map_err(to_io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_send_lines(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx).map_err(to_io_error)
+    }
+}
+
+fn to_io_error<E: Into<Box<dyn ::std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::other(err)
+}
+
+/// Connects to the relay at a `ws://` or `wss://` URL, e.g. `wss://example.com/weechat`.
+///
+/// `wss://` requires the `tls` feature.
+pub async fn connect_ws(url: String) -> Result<WsStream<Box<dyn Transport>>> {
+    let parsed = Url::parse(&url).chain_err(|| "invalid WebSocket URL")?;
+    let host = parsed.host_str().ok_or("WebSocket URL without host")?.to_owned();
+    let port = parsed.port_or_known_default().ok_or("WebSocket URL without port")?;
+
+    let stream = match parsed.scheme() {
+        "ws" => connect_plain(host, port).await?,
+        "wss" => connect_secure(host, port).await?,
+        scheme => bail!("unsupported WebSocket scheme '{}'", scheme),
+    };
+
+    let (stream, _) = client_async(url.as_str(), stream).await?;
+
+    Ok(WsStream::new(stream))
+}
+
+async fn connect_plain(host: String, port: u16) -> Result<Box<dyn Transport>> {
+    let stream = connect_tcp(host, port).await?;
+
+    Ok(Box::new(stream))
+}
+
+#[cfg(feature = "tls")]
+async fn connect_secure(host: String, port: u16) -> Result<Box<dyn Transport>> {
+    let stream = connect_tls(host, port, TlsConfig::new()).await?;
+
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect_secure(_host: String, _port: u16) -> Result<Box<dyn Transport>> {
+    bail!("wss:// requires the tls feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt,AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use crate::object::Object;
+    use crate::raw::{Compression,encode_message};
+
+    #[tokio::test]
+    async fn ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/weechat", listener.local_addr().unwrap());
+
+        let relay = tokio::spawn(async move {
+            let (conn, _) = listener.accept().await?;
+            let mut ws = accept_async(conn).await?;
+            let message = ws.next().await.transpose()?;
+            assert_eq!(message, Some(Message::text("ping hi\n")));
+
+            let pong = encode_message("_pong", &[Object::str("hi")], Compression::Off)?;
+            ws.send(Message::binary(pong)).await?;
+            Ok::<_, Error>(ws)
+        });
+
+        let mut stream = connect_ws(url).await.unwrap();
+        stream.write_all(b"ping hi\n").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut message = vec![0; 23];
+        stream.read_exact(&mut message).await.unwrap();
+
+        assert_eq!(message, &b"\0\0\0\x17\0\0\0\0\x05_pongstr\0\0\0\x02hi"[..]);
+        relay.await.unwrap().unwrap();
+    }
+}